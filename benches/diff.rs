@@ -0,0 +1,77 @@
+use bad_apple_flut::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+
+fn random_frame(rng: &mut StdRng) -> Frame {
+    let data = (0..WIDTH * HEIGHT)
+        .map(|_| Color::new(rng.gen(), rng.gen(), rng.gen()))
+        .collect::<Vec<_>>();
+    Frame::new(WIDTH, HEIGHT, data)
+}
+
+/// Per-pixel conversion of both frames, as the v2 compressor used to do
+fn per_pixel_scores(distance: ColorDistance, old: &Frame, new: &Frame) -> Vec<f32> {
+    old.data()
+        .par_iter()
+        .zip(new.data().par_iter())
+        .map(|(a, b)| distance.distance(a, b))
+        .collect()
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let old = random_frame(&mut rng);
+    let new = random_frame(&mut rng);
+
+    let mut group = c.benchmark_group("diff_1080p");
+    group.sample_size(20);
+
+    for distance in [ColorDistance::Rgb, ColorDistance::DeltaE76, ColorDistance::DeltaE2000] {
+        group.bench_with_input(BenchmarkId::new("per_pixel", format!("{:?}", distance)), &distance, |b, d| {
+            b.iter(|| per_pixel_scores(*d, &old, &new))
+        });
+
+        let old_planes = ColorPlanes::project(&old, distance);
+        let mut new_planes = ColorPlanes::new(distance);
+        let mut scores = Vec::new();
+        group.bench_function(BenchmarkId::new("cached_planes", format!("{:?}", distance)), |b| {
+            b.iter(|| {
+                new_planes.project_from(&new);
+                old_planes.scores_into(&new_planes, &mut scores);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_compress(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(1);
+    let frames = (0..4).map(|_| random_frame(&mut rng)).collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("compress_1080p");
+    group.sample_size(10);
+
+    for (alg, level) in [
+        (CompressionAlgConfig::V1, CompressionLevelArg::Medium),
+        (CompressionAlgConfig::V2, CompressionLevelArg::Number(20_000)),
+    ] {
+        let mut compressor = VideoCompressor::new(alg.clone(), level, &CompressorOptions::default()).unwrap();
+        compressor.compress_frame(&frames[0]);
+
+        let mut i = 0;
+        group.bench_function(format!("{:?}", alg), |b| {
+            b.iter(|| {
+                i = (i + 1) % frames.len();
+                compressor.compress_frame(&frames[i])
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_diff, bench_compress);
+criterion_main!(benches);
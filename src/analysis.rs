@@ -0,0 +1,152 @@
+use std::fmt::Display;
+use std::sync::{atomic::AtomicUsize, Arc};
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+
+use crate::{
+    args::CompressionLevelArg, frame::FrameFile, pixels_to_cmds, CompressionAlgConfig, CompressorOptions,
+    FrameMetrics, MetricsRecorder, MetricsSummary, OutputTransform, Protocol, Quantizer, Result,
+    VideoCompressor, VideoMetadata,
+};
+
+struct Candidate {
+    algorithm: CompressionAlgConfig,
+    level: String,
+    compressor: VideoCompressor,
+    pixels: usize,
+    bytes: Vec<usize>,
+    metrics: MetricsRecorder,
+}
+
+/// Results of compressing a video with a single algorithm/level combination
+#[derive(Debug, Clone)]
+pub struct AnalysisRow {
+    pub algorithm: CompressionAlgConfig,
+    pub level: String,
+    pub pixels_per_frame: f64,
+    /// Bytes per frame for every protocol, in the order of [`Protocol::value_variants`]
+    pub bytes_per_frame: Vec<(Protocol, f64)>,
+    pub metrics: Option<MetricsSummary>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub fps: f64,
+    pub frame_count: usize,
+    pub rows: Vec<AnalysisRow>,
+}
+
+impl Analysis {
+    /// Compresses every extracted frame with each algorithm at each of the given levels.
+    /// Levels that an algorithm does not support are skipped.
+    pub fn run(
+        metadata: &VideoMetadata,
+        levels: &[String],
+        options: &CompressorOptions,
+        mut quantizer: Option<Quantizer>,
+        x_offset: usize,
+        y_offset: usize,
+        counter: Arc<AtomicUsize>,
+    ) -> Result<Self> {
+        let protocols = Protocol::value_variants();
+
+        let mut candidates = CompressionAlgConfig::value_variants()
+            .iter()
+            .flat_map(|alg| levels.iter().map(move |level| (alg, level)))
+            .filter_map(|(alg, level)| {
+                let arg = CompressionLevelArg::try_from(level.clone()).ok()?;
+                let compressor = VideoCompressor::new(alg.clone(), arg.per_frame(metadata.fps), options).ok()?;
+                Some(Candidate {
+                    algorithm: alg.clone(),
+                    level: level.clone(),
+                    compressor,
+                    pixels: 0,
+                    bytes: vec![0; protocols.len()],
+                    metrics: MetricsRecorder::new(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for i in 1..=metadata.frame_count {
+            let frame = FrameFile::new(i).load()?;
+            let quantized = quantizer.as_mut().map(|q| q.process(&frame));
+
+            candidates.par_iter_mut().for_each(|c| {
+                let frame_data = c.compressor.compress_frame(quantized.as_ref().unwrap_or(&frame));
+
+                c.metrics.record(FrameMetrics::compute(
+                    i,
+                    frame_data.len(),
+                    &frame,
+                    c.compressor.canvas().expect("Canvas missing after compression").as_ref(),
+                ));
+                c.pixels += frame_data.len();
+
+                let pixels = frame_data.to_pixels();
+                let transform = OutputTransform::default();
+                for (bytes, protocol) in c.bytes.iter_mut().zip(protocols) {
+                    *bytes += pixels_to_cmds(*protocol, 0, &pixels, x_offset, y_offset, &transform, (0, 0)).len();
+                }
+            });
+
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        let frames = metadata.frame_count.max(1) as f64;
+        let rows = candidates
+            .into_iter()
+            .map(|c| AnalysisRow {
+                algorithm: c.algorithm,
+                level: c.level,
+                pixels_per_frame: c.pixels as f64 / frames,
+                bytes_per_frame: protocols
+                    .iter()
+                    .zip(c.bytes)
+                    .map(|(p, b)| (*p, b as f64 / frames))
+                    .collect(),
+                metrics: c.metrics.summary(),
+            })
+            .collect();
+
+        Ok(Self { fps: metadata.fps, frame_count: metadata.frame_count, rows })
+    }
+}
+
+fn value_name(value: &impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+impl Display for Analysis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} frames @ {:.2} fps", self.frame_count, self.fps)?;
+
+        write!(f, "{:<6} {:<16} {:>10}", "alg", "level", "px/frame")?;
+        for protocol in Protocol::value_variants() {
+            write!(f, " | {:>18} {:>10}", format!("{} B/f", value_name(protocol)), "Mbit/s")?;
+        }
+        writeln!(f, " | {:>9} {:>7}", "PSNR", "SSIM")?;
+
+        for row in &self.rows {
+            write!(
+                f,
+                "{:<6} {:<16} {:>10.1}",
+                value_name(&row.algorithm),
+                row.level,
+                row.pixels_per_frame
+            )?;
+            for (_, bytes) in &row.bytes_per_frame {
+                let mbits = bytes * self.fps * 8.0 / 1_000_000.0;
+                write!(f, " | {:>18.0} {:>10.2}", bytes, mbits)?;
+            }
+            match &row.metrics {
+                Some(m) => writeln!(f, " | {:>6.2} dB {:>7.4}", m.psnr, m.mean_ssim)?,
+                None => writeln!(f, " | {:>9} {:>7}", "-", "-")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,21 +1,106 @@
+use clap::{Parser, Subcommand};
 use clap_serde_derive::ClapSerde;
 use serde::{Deserialize, Serialize};
 
-use crate::{cache::CacheKey, CompressionAlgConfig, Protocol};
+use crate::{
+    cache::CacheKey, Color, ColorDistance, CompressionAlgConfig, Dither, Fit, Flip, Palette,
+    PixelOrder, Protocol, Quantizer, Rotation, Scaler, SpillCompression, Transition, VideoFilters,
+};
 
-#[derive(ClapSerde, Clone, Debug, Serialize, Deserialize)]
+#[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub args: <Args as ClapSerde>::Opt,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Compare compression settings offline, without connecting to a server
+    Analyze {
+        /// Compression levels to try with every algorithm that supports them
+        #[clap(long, value_delimiter = ',', default_value = DEFAULT_ANALYSIS_LEVELS)]
+        levels: Vec<String>,
+
+        #[command(flatten)]
+        args: <Args as ClapSerde>::Opt,
+    },
+    /// Send a recording made with --record to the target, without decoding the video
+    Replay {
+        /// Recording to send
+        recording: String,
+
+        /// Send as fast as possible instead of with the original timing
+        #[clap(long)]
+        fast: bool,
+
+        #[command(flatten)]
+        args: <Args as ClapSerde>::Opt,
+    },
+}
+
+pub const DEFAULT_JIT_LOOKAHEAD: usize = 4;
+
+pub const DEFAULT_ANALYSIS_LEVELS: &str = "none,low,medium,high,trash-compactor,256,512,768,1024,2048";
+
+#[derive(ClapSerde, Clone, Debug, Serialize, Deserialize)]
 pub struct Args {
-    /// Input file
+    /// Input file, several files are played back to back
     #[clap(short, long)]
     #[serde(skip)]
-    pub input: String,
+    pub input: Vec<String>,
+
+    /// Playlist file to play instead of the input files
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub playlist: Option<String>,
+
+    /// Play the input files in random order
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub shuffle: bool,
+
+    /// Transition between input files
+    #[clap(long)]
+    #[serde(default)]
+    pub transition: Transition,
+
+    /// Length of a transition (in s) [default: 1.0]
+    #[clap(long)]
+    pub transition_duration: Option<f64>,
     
-    /// Target section from config file to use
+    /// Target or target group sections from config file to use (comma-separated)
     #[clap(long)]
     #[serde(skip_serializing)]
     pub target: Option<String>,
 
+    /// Wall section from config file to use, splitting the video across several targets
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub wall: Option<String>,
+
+    /// Layer sections from config file to draw on top of the video (comma-separated)
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub layers: Option<String>,
+
+    /// Text sections from config file to draw on top of the video (comma-separated)
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub texts: Option<String>,
+
+    /// Subtitle file (SRT or ASS) to draw on top of the video
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub subtitles: Option<String>,
+
+    /// Size of a font pixel of the subtitles (in px) [default: frame height / 240]
+    #[clap(long)]
+    pub subtitle_scale: Option<usize>,
+
     /// Host to connect to
     #[clap(long)]
     #[serde(skip_serializing)]    
@@ -31,6 +116,11 @@ pub struct Args {
     #[serde(default)]
     pub canvas: u8,
 
+    /// Order in which the pixels of a frame are sent
+    #[clap(long)]
+    #[serde(default)]
+    pub pixel_order: PixelOrder,
+
     /// Horizontal offset (in px)
     #[clap(short)]  
     #[serde(default)]  
@@ -52,6 +142,48 @@ pub struct Args {
     /// Frame-rate (in fps) [default: same as source]
     #[clap(long)]
     pub fps: Option<f64>,
+
+    /// How the video is fit into --width x --height when both are given
+    #[clap(long)]
+    #[serde(default)]
+    pub fit: Fit,
+
+    /// Scaling algorithm, `neighbor` keeps pixel art sharp
+    #[clap(long)]
+    #[serde(default)]
+    pub scaler: Scaler,
+
+    /// Region of the source to play, as width:height:x:y (in source px)
+    #[clap(long)]
+    pub crop: Option<String>,
+
+    /// Rotate the source clockwise (in degrees)
+    #[clap(long)]
+    pub rotate: Option<Rotation>,
+
+    /// Mirror the source
+    #[clap(long)]
+    pub flip: Option<Flip>,
+
+    /// Brightness adjustment, -1.0 to 1.0 [default: 0.0]
+    #[clap(long, allow_negative_numbers = true)]
+    pub brightness: Option<f32>,
+
+    /// Contrast adjustment [default: 1.0]
+    #[clap(long)]
+    pub contrast: Option<f32>,
+
+    /// Saturation adjustment, 0.0 is grayscale [default: 1.0]
+    #[clap(long)]
+    pub saturation: Option<f32>,
+
+    /// Gamma adjustment [default: 1.0]
+    #[clap(long)]
+    pub gamma: Option<f32>,
+
+    /// Additional ffmpeg video filters, applied after the ones above and before scaling
+    #[clap(long)]
+    pub filters: Option<String>,
     
     /// Number of threads to use for sending pixels
     #[clap(long)]    
@@ -69,6 +201,32 @@ pub struct Args {
     #[clap(long)]
     pub compression_level: String,
 
+    /// Color distance metric used to rank pixel changes (v2 only)
+    #[clap(long)]
+    #[serde(default)]
+    pub color_distance: ColorDistance,
+
+    /// Luma threshold separating foreground from background (mono only) [default: 128]
+    #[clap(long)]
+    pub mono_threshold: Option<u8>,
+
+    /// Color of pixels brighter than the threshold (mono only) [default: ffffff]
+    #[clap(long)]
+    pub mono_fg: Option<Color>,
+
+    /// Color of pixels darker than the threshold (mono only) [default: 000000]
+    #[clap(long)]
+    pub mono_bg: Option<Color>,
+
+    /// Reduce frames to a fixed palette before compression [1bit|8color|web-safe|path to palette file]
+    #[clap(long)]
+    pub palette: Option<String>,
+
+    /// Dithering used when reducing frames to a palette
+    #[clap(long)]
+    #[serde(default)]
+    pub dither: Dither,
+
     /// Number of frames to group together when compressing ahead-of-time
     #[clap(long)]    
     pub aot_frame_group_size: usize,
@@ -83,43 +241,173 @@ pub struct Args {
     #[serde(default)]
     pub jit: bool,
     
+    /// Number of frames buffered between the decode, compress and send stages in JIT mode [default: 4]
+    #[clap(long)]
+    pub jit_lookahead: Option<usize>,
+
+    /// Spill compressed frames to disk instead of keeping them in RAM (ahead-of-time only)
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub spill: bool,
+
+    /// Compression of spilled frames
+    #[clap(long)]
+    #[serde(default)]
+    pub spill_compression: SpillCompression,
+
+    /// Play the audio track: `play` uses the audio player, anything else is a file or named pipe
+    /// to write raw PCM to (s16le, 48 kHz, stereo)
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub audio: Option<String>,
+
+    /// Command playing raw PCM from stdin [default: ffplay]
+    #[clap(long)]
+    pub audio_player: Option<String>,
+
+    /// Delay of the audio (in ms), to make up for the latency of the wall; may be negative
+    #[clap(long, allow_negative_numbers = true)]
+    #[serde(default)]
+    pub av_offset: i64,
+
+    /// Unix socket to listen on for control commands (pause, seek, ...)
+    #[clap(long)]
+    pub control: Option<String>,
+
+    /// Record everything sent to the servers, with timing, to a file for the replay command
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub record: Option<String>,
+
+    /// Render what the wall would show into a video file instead of sending to a server
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub export: Option<String>,
+
+    /// Play into the terminal instead of sending to a server
+    #[clap(long, alias = "preview", action=clap::ArgAction::SetTrue)]
+    #[serde(skip_serializing, default)]
+    pub dry_run: bool,
+
+    /// Show a terminal UI with playback stats and a preview of the canvas
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub tui: bool,
+
+    /// Compute PSNR/SSIM of the reconstructed canvas for every frame
+    #[clap(long, action=clap::ArgAction::SetTrue)]
+    #[serde(default)]
+    pub metrics: bool,
+
+    /// Write per-frame metrics to a CSV file (implies --metrics)
+    #[clap(long)]
+    #[serde(skip_serializing)]
+    pub metrics_csv: Option<String>,
+
     /// Enable debug output
     #[clap(long, action=clap::ArgAction::SetTrue)]
     #[serde(default)]
     pub debug: bool,
 }
 
-impl From<Args> for CacheKey {
-    fn from(args: Args) -> Self {
-        Self::new(
-            args.input,
-            args.width.unwrap_or(0),
-            args.height.unwrap_or(0),
-            args.fps.unwrap_or(0.0)
+impl Args {
+    /// Key of the frame cache of `input`, extracted with these args
+    pub fn cache_key(&self, input: &str) -> CacheKey {
+        CacheKey::new(
+            input.to_string(),
+            self.width.unwrap_or(0),
+            self.height.unwrap_or(0),
+            self.fps.unwrap_or(0.0)
         )
+        .with_filters(&self.video_filters())
+    }
+
+    /// Filters applied when extracting frames
+    pub fn video_filters(&self) -> VideoFilters {
+        VideoFilters {
+            crop: self.crop.clone(),
+            rotate: self.rotate,
+            flip: self.flip,
+            brightness: self.brightness,
+            contrast: self.contrast,
+            saturation: self.saturation,
+            gamma: self.gamma,
+            custom: self.filters.clone(),
+            scaler: self.scaler,
+            fit: self.fit,
+        }
+    }
+
+    #[inline] pub fn metrics_enabled(&self) -> bool {
+        self.metrics || self.metrics_csv.is_some()
+    }
+
+    /// Creates the palette quantizer, if a palette was specified
+    pub fn quantizer(&self) -> crate::Result<Option<Quantizer>> {
+        self.palette
+            .clone()
+            .map(|p| Ok(Quantizer::new(&Palette::try_from(p)?, self.dither)))
+            .transpose()
     }
-}
 
-impl Args {
     pub fn config_default() -> Self {
         Self {
-            input: "".to_string(), // will be skipped by serde
+            input: Vec::new(), // will be skipped by serde
+            playlist: None,
+            shuffle: false,
+            transition: Transition::default(),
+            transition_duration: None,
             host: None,
             target: None,
+            wall: None,
+            layers: None,
+            texts: None,
+            subtitles: None,
+            subtitle_scale: None,
             x_offset: 0,
             y_offset: 0,
             width: None,
             height: None,
             fps: None,
+            fit: Fit::default(),
+            scaler: Scaler::default(),
+            crop: None,
+            rotate: None,
+            flip: None,
+            brightness: None,
+            contrast: None,
+            saturation: None,
+            gamma: None,
+            filters: None,
             protocol: Protocol::default(),
             canvas: 0,
+            pixel_order: PixelOrder::default(),
             nocache: false,
             jit: false,
+            jit_lookahead: None,
+            spill: false,
+            spill_compression: SpillCompression::default(),
+            control: None,
+            audio: None,
+            audio_player: None,
+            av_offset: 0,
+            record: None,
+            export: None,
+            dry_run: false,
+            tui: false,
+            metrics: false,
+            metrics_csv: None,
             debug: false,
             send_threads: 4,
             aot_frame_group_size: 100,
             compression_algorithm: CompressionAlgConfig::V2,
             compression_level: "768".to_string(),
+            color_distance: ColorDistance::default(),
+            mono_threshold: None,
+            mono_fg: None,
+            mono_bg: None,
+            palette: None,
+            dither: Dither::default(),
             compress_threads: 4,
         }
     }
@@ -135,6 +423,57 @@ pub enum CompressionLevelArg {
     Number(usize)
 }
 
+impl CompressionLevelArg {
+    /// Converts a pixel-rate level (in kpx/s) to pixels per frame
+    pub fn per_frame(self, fps: f64) -> Self {
+        match self {
+            Self::Number(n) => Self::Number(((n * 1024) as f64 / fps) as usize),
+            level => level,
+        }
+    }
+
+    /// Pixels per frame of a level given as a number, `None` for named levels and 0 (unlimited)
+    pub fn pixel_budget(&self) -> Option<usize> {
+        match self {
+            Self::Number(n) if *n > 0 => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The next level with a larger (`more`) or smaller pixel budget. Levels are named from the
+    /// largest budget (`none`) to the smallest (`trash-compactor`); numbers change by 25%.
+    pub fn step(self, more: bool) -> Self {
+        use CompressionLevelArg::*;
+        match (self, more) {
+            // 0 is unlimited
+            (Number(0), _) => Number(0),
+            (Number(n), true) => Number(n + (n / 4).max(1)),
+            (Number(n), false) => Number((n - (n / 5).max(1)).max(1)),
+            (TrashCompactor, true) => High,
+            (High, true) => Medium,
+            (Medium, true) => Low,
+            (Low | None, true) => None,
+            (None, false) => Low,
+            (Low, false) => Medium,
+            (Medium, false) => High,
+            (High | TrashCompactor, false) => TrashCompactor,
+        }
+    }
+}
+
+impl std::fmt::Display for CompressionLevelArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Low => write!(f, "low"),
+            Self::Medium => write!(f, "medium"),
+            Self::High => write!(f, "high"),
+            Self::TrashCompactor => write!(f, "trash-compactor"),
+            Self::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
 impl TryFrom<String> for CompressionLevelArg {
     type Error = &'static str;
 
@@ -0,0 +1,207 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, Stdio};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::{Error, Result};
+
+pub const AUDIO_SAMPLE_RATE: u32 = 48000;
+pub const AUDIO_CHANNELS: u32 = 2;
+/// Bytes of one sample of every channel (signed 16 bit little endian)
+const SAMPLE_SIZE: usize = 2 * AUDIO_CHANNELS as usize;
+/// Largest jump of the video clock (in s) that is played through, larger jumps are seeks
+const MAX_GAP: f64 = 1.0;
+
+pub const DEFAULT_AUDIO_PLAYER: &str = "ffplay";
+
+/// Audio track of a video as raw PCM, see [`AUDIO_SAMPLE_RATE`] and [`AUDIO_CHANNELS`]
+pub struct AudioTrack {
+    samples: Vec<u8>,
+}
+
+impl AudioTrack {
+    pub fn new(samples: Vec<u8>) -> Self {
+        Self { samples }
+    }
+
+    /// The samples from sample `start` to `end`. Parts before the start or after the end of the
+    /// track are silent.
+    fn slice(&self, start: i64, end: i64) -> Vec<u8> {
+        let len = (self.samples.len() / SAMPLE_SIZE) as i64;
+        let mut out = vec![0; (end - start).max(0) as usize * SAMPLE_SIZE];
+        let (from, to) = (start.clamp(0, len), end.clamp(0, len));
+        if from < to {
+            let dst = (from - start) as usize * SAMPLE_SIZE;
+            let src = &self.samples[from as usize * SAMPLE_SIZE..to as usize * SAMPLE_SIZE];
+            out[dst..dst + src.len()].copy_from_slice(src);
+        }
+        out
+    }
+}
+
+/// Index of the sample played at `secs`
+fn sample_at(secs: f64) -> i64 {
+    (secs * AUDIO_SAMPLE_RATE as f64).round() as i64
+}
+
+/// Plays audio along with the video (`--audio`), through a player process or by writing raw PCM
+/// to a file or named pipe. The audio follows the video clock: every sent frame releases the
+/// audio up to its end, so pausing, seeking and lagging playback move the audio along.
+pub struct AudioOutput {
+    chunks: Option<Sender<Vec<u8>>>,
+    writer: Option<JoinHandle<()>>,
+    player: Option<Child>,
+    /// Delay of the audio (in s), to make up for the latency of the wall
+    offset: f64,
+    /// Next sample to write, `None` before the first frame
+    position: Option<i64>,
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl AudioOutput {
+    /// Plays through `player` if `target` is `play`, otherwise writes to the file or named pipe
+    /// `target`. `offset` delays the audio (in ms, may be negative).
+    pub fn open(target: &str, player: &str, offset: i64) -> Result<Self> {
+        let (sink, child): (Box<dyn Write + Send>, _) = match target {
+            "play" => {
+                let mut child = player_command(player)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .map_err(|e| Error::Custom(format!("Failed to start audio player '{}': {}", player, e)))?;
+                (Box::new(child.stdin.take().unwrap()), Some(child))
+            }
+            path => {
+                let file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(Path::new(path))
+                    .map_err(|e| Error::Custom(format!("Failed to open audio output {}: {}", path, e)))?;
+                (Box::new(file), None)
+            }
+        };
+
+        // writes can block (e.g. on a full pipe), which must not hold up sending frames
+        let (tx, rx) = channel::<Vec<u8>>();
+        let error = Arc::new(Mutex::new(None));
+        let writer = {
+            let error = Arc::clone(&error);
+            let mut sink = sink;
+            thread::spawn(move || {
+                for chunk in rx {
+                    if let Err(e) = sink.write_all(&chunk).and_then(|_| sink.flush()) {
+                        *error.lock().unwrap() = Some(format!("Audio output failed: {}", e));
+                        return;
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            chunks: Some(tx),
+            writer: Some(writer),
+            player: child,
+            offset: offset as f64 / 1000.0,
+            position: None,
+            error,
+        })
+    }
+
+    /// Releases the audio of a frame that was just sent, shown from `start` to `end` (in s) on
+    /// the video clock. Fails once if the output broke down.
+    pub fn sync(&mut self, track: &AudioTrack, start: f64, end: f64) -> Result<()> {
+        if let Some(e) = self.error.lock().unwrap().take() {
+            self.chunks = None;
+            return Err(Error::Custom(e));
+        }
+        let Some(chunks) = &self.chunks else { return Ok(()) };
+
+        let end = sample_at(end - self.offset);
+        let position = match self.position {
+            Some(p) if p <= end && ((end - p) as f64) < MAX_GAP * AUDIO_SAMPLE_RATE as f64 => p,
+            // seeked, or started over
+            _ => sample_at(start - self.offset),
+        };
+        if position < end {
+            // the writer is gone after an error, which is reported on the next frame
+            let _ = chunks.send(track.slice(position, end));
+        }
+        self.position = Some(end);
+        Ok(())
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        self.chunks = None;
+        if let Some(player) = &mut self.player {
+            let _ = player.kill();
+            let _ = player.wait();
+        }
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Command running `player`, reading raw PCM from stdin. Known players without arguments get the
+/// arguments for the raw format, other commands are run as given.
+fn player_command(player: &str) -> std::process::Command {
+    let mut parts = player.split_whitespace();
+    let program = parts.next().unwrap_or(DEFAULT_AUDIO_PLAYER);
+    let mut cmd = std::process::Command::new(program);
+    let args = parts.collect::<Vec<_>>();
+    if !args.is_empty() {
+        cmd.args(args);
+        return cmd;
+    }
+
+    let rate = AUDIO_SAMPLE_RATE.to_string();
+    let channels = AUDIO_CHANNELS.to_string();
+    let name = Path::new(program).file_name().and_then(|n| n.to_str()).unwrap_or(program);
+    match name {
+        "ffplay" => cmd.args([
+            "-nodisp", "-autoexit", "-loglevel", "quiet",
+            "-f", "s16le", "-ar", &rate, "-ch_layout", "stereo", "-i", "-",
+        ]),
+        "aplay" => cmd.args(["-q", "-t", "raw", "-f", "S16_LE", "-r", &rate, "-c", &channels, "-"]),
+        "pacat" | "paplay" => cmd.args([
+            "--raw", "--format=s16le", &format!("--rate={}", rate), &format!("--channels={}", channels),
+        ]),
+        _ => &mut cmd,
+    };
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_follows_video_clock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audio.pcm");
+        // one second of audio, every sample holding the low byte of its index
+        let samples = (0..AUDIO_SAMPLE_RATE).flat_map(|i| [(i % 256) as u8; SAMPLE_SIZE]).collect();
+        let track = AudioTrack::new(samples);
+
+        let mut output = AudioOutput::open(path.to_str().unwrap(), DEFAULT_AUDIO_PLAYER, 100).unwrap();
+        // 10 fps: 4800 samples per frame, the first 100 ms are silent because of the offset
+        output.sync(&track, 0.0, 0.1).unwrap();
+        output.sync(&track, 0.1, 0.2).unwrap();
+        // seek back to the first frame
+        output.sync(&track, 0.0, 0.1).unwrap();
+        drop(output);
+
+        let written = std::fs::read(&path).unwrap();
+        let frame = 4800 * SAMPLE_SIZE;
+        assert_eq!(written.len(), 3 * frame);
+        assert!(written[..frame].iter().all(|b| *b == 0));
+        assert_eq!(written[frame..2 * frame], track.samples[..frame]);
+        assert!(written[2 * frame..].iter().all(|b| *b == 0));
+    }
+}
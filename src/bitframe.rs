@@ -0,0 +1,114 @@
+use rayon::prelude::*;
+
+use crate::{frame::Frame, Color};
+
+/// 1-bit frame, one bit per pixel in row-major order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitFrame {
+    width: usize,
+    height: usize,
+    words: Box<[u64]>,
+}
+
+impl BitFrame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            words: vec![0; (width * height).div_ceil(64)].into(),
+        }
+    }
+
+    /// Sets every pixel whose luma is above `threshold`
+    pub fn threshold(frame: &Frame, threshold: u8) -> Self {
+        let mut bits = Self::new(frame.width(), frame.height());
+        let threshold = threshold as u32 * 1000;
+
+        bits.words
+            .par_iter_mut()
+            .zip(frame.data().par_chunks(64))
+            .for_each(|(word, colors)| {
+                for (i, c) in colors.iter().enumerate() {
+                    // BT.601 luma, scaled by 1000
+                    let luma = c.r as u32 * 299 + c.g as u32 * 587 + c.b as u32 * 114;
+                    *word |= ((luma > threshold) as u64) << i;
+                }
+            });
+        bits
+    }
+
+    #[inline] pub fn width(&self) -> usize { self.width }
+    #[inline] pub fn height(&self) -> usize { self.height }
+    #[inline] pub fn words(&self) -> &[u64] { &self.words }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> bool {
+        self.words[i / 64] >> (i % 64) & 1 != 0
+    }
+    #[inline]
+    pub fn set(&mut self, i: usize, value: bool) {
+        let mask = 1 << (i % 64);
+        if value {
+            self.words[i / 64] |= mask;
+        } else {
+            self.words[i / 64] &= !mask;
+        }
+    }
+
+    /// Indices of the pixels that differ between the two frames, starting at word `start` and
+    /// wrapping around to the beginning of the frame
+    pub fn diff<'a>(&'a self, other: &'a BitFrame, start: usize) -> impl Iterator<Item = usize> + 'a {
+        assert_eq!(self.words.len(), other.words.len(), "frames have different sizes");
+        let n = self.words.len();
+
+        (0..n)
+            .map(move |w| (w + start) % n)
+            .flat_map(move |w| {
+                let mut xor = self.words[w] ^ other.words[w];
+                std::iter::from_fn(move || {
+                    if xor == 0 {
+                        return None;
+                    }
+                    let bit = xor.trailing_zeros() as usize;
+                    xor &= xor - 1;
+                    Some(w * 64 + bit)
+                })
+            })
+    }
+
+    /// Number of pixels that differ between the two frames
+    pub fn count_diff(&self, other: &BitFrame) -> usize {
+        self.words
+            .par_iter()
+            .zip(other.words.par_iter())
+            .map(|(a, b)| (a ^ b).count_ones() as usize)
+            .sum()
+    }
+
+    pub fn to_frame(&self, fg: Color, bg: Color) -> Frame {
+        let data = (0..self.width * self.height)
+            .into_par_iter()
+            .map(|i| if self.get(i) { fg } else { bg })
+            .collect::<Vec<_>>();
+        Frame::new(self.width, self.height, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let mut a = BitFrame::new(100, 3);
+        let mut b = BitFrame::new(100, 3);
+        for i in [0, 63, 64, 130, 299] {
+            b.set(i, true);
+        }
+        a.set(130, true);
+
+        assert_eq!(a.diff(&b, 0).collect::<Vec<_>>(), vec![0, 63, 64, 299]);
+        assert_eq!(a.diff(&b, 1).collect::<Vec<_>>(), vec![64, 299, 0, 63]);
+        assert_eq!(a.count_diff(&b), 4);
+    }
+}
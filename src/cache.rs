@@ -1,10 +1,11 @@
 use std::hash::{Hasher, Hash};
 use std::fs::File;
 use std::io::{BufReader, BufRead};
+use std::path::{Path, PathBuf};
 
 use serde::{Serialize, Deserialize};
 
-use crate::{paths, Result, Error};
+use crate::{audio::AudioTrack, extract_audio, extract_video_frames, get_video_framerate, paths, Result, Error, VideoFilters};
 
 #[derive(Debug, Hash)]
 pub struct CacheKey {
@@ -12,6 +13,8 @@ pub struct CacheKey {
     width: i32,
     height: i32,
     fps: u64,
+    /// See [`VideoFilters::cache_key`]
+    filters: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,10 +23,8 @@ pub struct VideoMetadata {
     pub frame_count: usize,
 }
 impl VideoMetadata {
-    pub fn load() -> Result<Self> {
-        let raw = std::fs::read_to_string(
-            paths::cache_metadata()
-        )?;
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
 
         toml::from_str(&raw)
             .map_err(|e| Error::FileParseError(e.to_string()))
@@ -31,14 +32,11 @@ impl VideoMetadata {
     pub fn create(fps: f64, frame_count: usize) -> Self {
         Self { fps, frame_count }
     }
-    pub fn write(&self) -> Result<()> {
+    pub fn write(&self, path: &Path) -> Result<()> {
         let raw = toml::to_string(self)
             .map_err(|e| Error::FileParseError(e.to_string()))?;
 
-        std::fs::write(
-            paths::cache_metadata(),
-            raw
-        )?;
+        std::fs::write(path, raw)?;
 
         Ok(())
     }
@@ -51,8 +49,15 @@ impl CacheKey {
             width,
             height,
             fps: (fps * (10.0_f64.powi(6))).round() as u64,
+            filters: VideoFilters::default().cache_key(),
         }
     }
+
+    /// Key of frames extracted with `filters`
+    pub fn with_filters(mut self, filters: &VideoFilters) -> Self {
+        self.filters = filters.cache_key();
+        self
+    }
 }
 
 
@@ -62,37 +67,140 @@ pub fn gen_cache_id(key: &CacheKey) -> u64 {
     hasher.finish()
 }
 
-pub fn write_cache_id(key: &CacheKey) -> Result<()> {
-    std::fs::write(
-        paths::cache_id(),
-        gen_cache_id(key).to_string()
-    )?;
-
-    Ok(())
+/// Directory holding the extracted frames of one input, along with its metadata and cache id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameCache {
+    dir: PathBuf,
 }
 
-pub fn is_cache_valid(key: &CacheKey) -> std::io::Result<bool> {
-    if !std::path::Path::new(&paths::cache_id()).exists() {
-        return Ok(false);
+impl FrameCache {
+    /// Cache of the main input
+    pub fn primary() -> Self {
+        Self { dir: paths::cache() }
+    }
+    /// Cache of an additional input (e.g. a layer). Every input gets its own directory, so it
+    /// survives changes to the main input.
+    pub fn input(key: &CacheKey) -> Self {
+        Self { dir: paths::cache_inputs().join(format!("{:016x}", gen_cache_id(key))) }
     }
 
-    let hash = gen_cache_id(key);
+    pub fn frames_dir(&self) -> PathBuf {
+        self.dir.join("frames")
+    }
+    pub fn frame_file(&self, idx: usize) -> PathBuf {
+        self.frames_dir().join(format!("frame{}.ppm", idx))
+    }
+    /// Audio track as raw PCM, empty if the input has none
+    pub fn audio_file(&self) -> PathBuf {
+        self.dir.join("audio.pcm")
+    }
+    fn id_file(&self) -> PathBuf {
+        self.dir.join("cache_id")
+    }
+    fn metadata_file(&self) -> PathBuf {
+        self.dir.join("metadata")
+    }
 
-    let cache_id = File::open(paths::cache_id())?;
-    let mut reader = BufReader::new(cache_id);
-    let mut line = String::new();
-    reader.read_line(&mut line)?;
-    let old_hash = line.parse::<u64>()
-        .map_err(|_| std::io::Error::new(
-            std::io::ErrorKind::InvalidData, "invalid cache id"
-        ))?;
+    pub fn metadata(&self) -> Result<VideoMetadata> {
+        VideoMetadata::load(&self.metadata_file())
+    }
 
-    Ok(hash == old_hash)
-}
+    pub fn write_id(&self, key: &CacheKey) -> Result<()> {
+        std::fs::write(
+            self.id_file(),
+            gen_cache_id(key).to_string()
+        )?;
 
-pub fn clean_cache() -> Result<()> {
-    if paths::cache().exists() {
-        std::fs::remove_dir_all(paths::cache())?;
+        Ok(())
     }
-    Ok(())
-}
\ No newline at end of file
+
+    pub fn is_valid(&self, key: &CacheKey) -> std::io::Result<bool> {
+        if !self.id_file().exists() {
+            return Ok(false);
+        }
+
+        let hash = gen_cache_id(key);
+
+        let cache_id = File::open(self.id_file())?;
+        let mut reader = BufReader::new(cache_id);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let old_hash = line.parse::<u64>()
+            .map_err(|_| std::io::Error::new(
+                std::io::ErrorKind::InvalidData, "invalid cache id"
+            ))?;
+
+        Ok(hash == old_hash)
+    }
+
+    /// Removes the cached frames. Cleaning the primary cache leaves the caches of other inputs
+    /// alone.
+    pub fn clean(&self) -> Result<()> {
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        if *self != Self::primary() {
+            std::fs::remove_dir_all(&self.dir)?;
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path == paths::cache_inputs() {
+                continue;
+            }
+            match path.is_dir() {
+                true => std::fs::remove_dir_all(path)?,
+                false => std::fs::remove_file(path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts the frames of `input` into this cache, unless it already holds them.
+    /// `fps`, `width`, `height`, `filters` and `progress` are passed to ffmpeg, see
+    /// [`extract_video_frames`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn prepare(
+        &self,
+        key: &CacheKey,
+        input: &str,
+        fps: Option<f64>,
+        width: i32,
+        height: i32,
+        filters: &VideoFilters,
+        force: bool,
+        progress: bool,
+    ) -> Result<VideoMetadata> {
+        if !self.is_valid(key).unwrap_or(false) || force {
+            self.clean()?;
+
+            let fps = match fps {
+                Some(fps) => fps,
+                None => get_video_framerate(input).await?,
+            };
+            let metadata = extract_video_frames(input, fps, width, height, filters, &self.frames_dir(), progress).await?;
+
+            metadata.write(&self.metadata_file())?;
+
+            self.write_id(key)?;
+        }
+
+        self.metadata()
+    }
+
+    /// Extracts the audio track of `input` into this cache, unless it already holds it. Call after
+    /// [`FrameCache::prepare`], which removes the audio of another input. Returns `None` if the
+    /// input has no audio track.
+    pub async fn prepare_audio(&self, input: &str) -> Result<Option<AudioTrack>> {
+        let path = self.audio_file();
+        if !path.exists() {
+            paths::create_dir_if_not_exists(&self.dir);
+            if !extract_audio(input, &path).await? {
+                // remember that there is none
+                std::fs::write(&path, [])?;
+            }
+        }
+        let samples = std::fs::read(&path)?;
+        Ok((!samples.is_empty()).then(|| AudioTrack::new(samples)))
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+
+use crate::{
+    frame::{Frame, FrameData},
+    FrameMetrics, Pixel,
+};
+
+/// A group of consecutive frames, compressed in order by a fresh compressor. The first frame of a
+/// chunk is a keyframe, since the compressor doesn't know what the wall shows.
+#[derive(Debug, Clone)]
+pub struct CompressedChunk {
+    pub frames: Vec<FrameData>,
+    /// Empty unless metrics are enabled
+    pub metrics: Vec<FrameMetrics>,
+    /// Reconstructed canvas after the last frame of the chunk
+    pub canvas: Option<Frame>,
+}
+
+/// Replaces the keyframe at the start of every chunk with a delta against what the wall shows at
+/// the end of the previous chunk, so only the pixels that actually changed are sent. The delta is
+/// spread over the frames of the chunk, every frame getting at most `budget` pixels (if any), and
+/// whatever doesn't fit by the end of the chunk is left to the next one. `previous` is what the wall
+/// shows before `chunks[0]`, if known. Returns what the wall shows after the last chunk.
+pub fn stitch_chunks(
+    previous: Option<Frame>,
+    chunks: &mut [CompressedChunk],
+    budget: Option<usize>,
+) -> Option<Frame> {
+    let mut wall = previous;
+    for chunk in chunks.iter_mut() {
+        let Some(mut shown) = wall.take() else {
+            wall = chunk.canvas.clone();
+            continue;
+        };
+        if let Some(keyframe @ FrameData::Full { .. }) = chunk.frames.first_mut() {
+            // a keyframe of a different size stays, as there is nothing to diff against
+            let remainder = match shown.diff(&Frame::from(keyframe.clone())) {
+                FrameData::Full { .. } => None,
+                delta => Some(delta.to_pixels()),
+            };
+            if let Some(remainder) = remainder {
+                *keyframe = FrameData::Empty;
+                spread_remainder(chunk, remainder, budget);
+            }
+        }
+        for frame in &chunk.frames {
+            shown.apply_frame_data_mut(frame);
+        }
+        wall = Some(shown);
+    }
+    wall
+}
+
+/// Adds the pixels of `remainder` to the frames of the chunk, oldest first, as far as the budget
+/// of every frame allows. Pixels a frame sets itself are dropped, as they are up to date anyway.
+fn spread_remainder(chunk: &mut CompressedChunk, mut remainder: Vec<Pixel>, budget: Option<usize>) {
+    for (i, frame) in chunk.frames.iter_mut().enumerate() {
+        // the first frame is the replaced keyframe, its metrics still need updating
+        if remainder.is_empty() && i > 0 {
+            break;
+        }
+        if let FrameData::Full { .. } = frame {
+            // a later keyframe redraws everything
+            return;
+        }
+        let own = std::mem::replace(frame, FrameData::Empty).to_pixels();
+        let set = own.iter().map(|p| (p.x(), p.y())).collect::<HashSet<_>>();
+        remainder.retain(|p| !set.contains(&(p.x(), p.y())));
+
+        let room = budget.map_or(usize::MAX, |b| b.saturating_sub(own.len())).min(remainder.len());
+        let mut pixels = remainder.drain(..room).collect::<Vec<_>>();
+        pixels.extend(own);
+        if let Some(m) = chunk.metrics.get_mut(i) {
+            m.pixels = pixels.len();
+        }
+        *frame = if pixels.is_empty() { FrameData::Empty } else { FrameData::Delta(pixels) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    fn chunk(first: FrameData, canvas: Frame) -> CompressedChunk {
+        CompressedChunk { frames: vec![first], metrics: Vec::new(), canvas: Some(canvas) }
+    }
+
+    #[test]
+    fn test_stitch_replaces_keyframes() {
+        let a = Frame::debug(8, 4);
+        let mut b = a.clone();
+        for i in [0, 9, 31] {
+            b.data_mut()[i] = Color::new(1, 2, 3);
+        }
+
+        let mut chunks = vec![
+            chunk(a.to_full_frame_data(), a.clone()),
+            chunk(b.to_full_frame_data(), b.clone()),
+            chunk(b.to_full_frame_data(), b.clone()),
+        ];
+        stitch_chunks(None, &mut chunks, None);
+
+        // the first chunk has nothing to stitch against
+        assert!(matches!(chunks[0].frames[0], FrameData::Full { .. }));
+        assert_eq!(chunks[1].frames[0].len(), 3);
+        assert_eq!(a.apply_frame_data(&chunks[1].frames[0]).data(), b.data());
+        assert!(chunks[2].frames[0].is_empty());
+
+        // continuing from an earlier batch of chunks
+        let mut next = vec![chunk(a.to_full_frame_data(), a.clone())];
+        let wall = stitch_chunks(Some(b.clone()), &mut next, None);
+        assert_eq!(b.apply_frame_data(&next[0].frames[0]).data(), a.data());
+        assert_eq!(wall.unwrap().data(), a.data());
+    }
+
+    #[test]
+    fn test_stitch_keeps_budget() {
+        use crate::{args::CompressionLevelArg, CompressionAlgConfig, CompressorOptions, VideoCompressor};
+
+        let budget = 10;
+        let compressor = || VideoCompressor::new(
+            CompressionAlgConfig::V2,
+            CompressionLevelArg::Number(budget),
+            &CompressorOptions::default(),
+        ).unwrap();
+        let frames = (0..8u8)
+            .map(|k| {
+                let mut frame = Frame::new(16, 8, vec![Color::new(0, 0, 0); 16 * 8]);
+                frame.data_mut()[..16 * k as usize].fill(Color::new(10 * k, 0, 0));
+                frame
+            })
+            .collect::<Vec<_>>();
+
+        let mut sequential = compressor();
+        let sequential = frames.iter().map(|f| sequential.compress_frame(f)).collect::<Vec<_>>();
+
+        let mut chunks = frames
+            .chunks(4)
+            .map(|frames| {
+                let mut compressor = compressor();
+                CompressedChunk {
+                    frames: frames.iter().map(|f| compressor.compress_frame(f)).collect(),
+                    metrics: Vec::new(),
+                    canvas: compressor.canvas().map(|c| c.into_owned()),
+                }
+            })
+            .collect::<Vec<_>>();
+        let wall = stitch_chunks(None, &mut chunks, Some(budget)).unwrap();
+        let stitched = chunks.iter().flat_map(|c| &c.frames).collect::<Vec<_>>();
+
+        // after the first keyframe, no stitched frame is larger than the frames of one compressor
+        for (stitched, sequential) in stitched.iter().zip(&sequential).skip(1) {
+            assert!(stitched.len() <= budget);
+            assert!(sequential.len() <= budget);
+        }
+        let shown = stitched
+            .iter()
+            .skip(1)
+            .fold(Frame::from(stitched[0].clone()), |f, d| f.apply_frame_data(d));
+        assert_eq!(shown.data(), wall.data());
+    }
+}
@@ -1,4 +1,10 @@
 
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Color { pub r: u8, pub g: u8, pub b: u8 }
 
@@ -7,6 +13,19 @@ impl Color {
     pub fn new(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b }
     }
+
+    /// Parses a hex color (`rrggbb` or `#rrggbb`)
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.trim().trim_start_matches('#');
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(Self::new((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+    pub fn to_hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
     /// Converts RGB to YUV
     // https://en.wikipedia.org/wiki/Y%E2%80%B2UV#Conversion_to/from_RGB
     pub fn to_yuv(&self) -> (u8, u8, u8) {
@@ -19,30 +38,242 @@ impl Color {
         (l as u8, u as u8, v as u8)    
     }
 
-    pub fn to_cielab(&self) -> (u8, u8, u8) {
-        let r = self.r as f32 / 255.0; let g = self.g as f32 / 255.0; let b = self.b as f32 / 255.0;
-        
-        let r = if r > 0.04045 { ((r + 0.055) / 1.055).powf(2.4) } else { r / 12.92 };
-        let g = if g > 0.04045 { ((g + 0.055) / 1.055).powf(2.4) } else { g / 12.92 };
-        let b = if b > 0.04045 { ((b + 0.055) / 1.055).powf(2.4) } else { b / 12.92 };
+    /// Converts sRGB to CIELAB (D65 white point)
+    // http://www.brucelindbloom.com/index.html?Eqn_RGB_to_XYZ.html
+    pub fn to_cielab(&self) -> Lab {
+        let lut = &*SRGB_TO_LINEAR;
+        let r = lut[self.r as usize]; let g = lut[self.g as usize]; let b = lut[self.b as usize];
 
         let x = r * 0.4124564 + g * 0.3575761 + b * 0.1804375;
-        let y = r * 0.2126729 + g * 0.7151522 + b * 0.0721750;
-        let z = r * 0.0193339 + g * 0.1191920 + b * 0.9503041;
+        let y = r * 0.2126729 + g * 0.7151522 + b * 0.072175;
+        let z = r * 0.0193339 + g * 0.119192 + b * 0.9503041;
 
-        let x = x / 0.95047;
-        let y = y / 1.0;
-        let z = z / 1.08883;
+        let fx = lab_f(x / 0.95047);
+        let fy = lab_f(y);
+        let fz = lab_f(z / 1.08883);
 
-        let x = if x > 0.008856 { x.powf(1.0 / 3.0) } else { (903.3 * x + 16.0) / 116.0 };
-        let y = if y > 0.008856 { y.powf(1.0 / 3.0) } else { (903.3 * y + 16.0) / 116.0 };
-        let z = if z > 0.008856 { z.powf(1.0 / 3.0) } else { (903.3 * z + 16.0) / 116.0 };
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+}
 
-        let l = (116.0 * y) - 16.0;
-        let a = 500.0 * (x - y);
-        let b = 200.0 * (y - z);
+impl FromStr for Color {
+    type Err = String;
 
-        (l as u8, a as u8, b as u8)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s).ok_or_else(|| format!("'{}' is not a valid hex color (rrggbb)", s))
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// sRGB gamma expansion for every possible channel value
+static SRGB_TO_LINEAR: LazyLock<[f32; 256]> = LazyLock::new(|| {
+    std::array::from_fn(|i| {
+        let c = i as f32 / 255.0;
+        if c > 0.04045 { ((c + 0.055) / 1.055).powf(2.4) } else { c / 12.92 }
+    })
+});
+
+const LAB_F_STEPS: usize = 4096;
+
+/// CIELAB companding function sampled over [0, 1], linearly interpolated by [`lab_f`]
+static LAB_F: LazyLock<[f32; LAB_F_STEPS + 2]> = LazyLock::new(|| {
+    std::array::from_fn(|i| lab_f_exact(i as f32 / LAB_F_STEPS as f32))
+});
+
+fn lab_f_exact(t: f32) -> f32 {
+    const EPSILON: f32 = 216.0 / 24389.0;
+    const KAPPA: f32 = 24389.0 / 27.0;
+    if t > EPSILON { t.cbrt() } else { (KAPPA * t + 16.0) / 116.0 }
+}
+
+#[inline]
+fn lab_f(t: f32) -> f32 {
+    if !(0.0..=1.0).contains(&t) {
+        return lab_f_exact(t);
+    }
+    let pos = t * LAB_F_STEPS as f32;
+    let i = pos as usize;
+    let frac = pos - i as f32;
+    let lut = &*LAB_F;
+    lut[i] + (lut[i + 1] - lut[i]) * frac
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Lab { pub l: f32, pub a: f32, pub b: f32 }
+
+impl Lab {
+    #[inline]
+    pub fn new(l: f32, a: f32, b: f32) -> Self {
+        Self { l, a, b }
+    }
+
+    /// CIE76 color difference (euclidean distance in Lab space)
+    #[inline]
+    pub fn delta_e76(&self, other: &Lab) -> f32 {
+        let dl = self.l - other.l;
+        let da = self.a - other.a;
+        let db = self.b - other.b;
+        (dl * dl + da * da + db * db).sqrt()
+    }
+
+    /// CIEDE2000 color difference
+    // https://hajim.rochester.edu/ece/sites/gsharma/ciede2000/ciede2000noteCRNA.pdf
+    pub fn delta_e2000(&self, other: &Lab) -> f32 {
+        use std::f32::consts::PI;
+        const POW25_7: f32 = 6103515625.0; // 25^7
+
+        let (l1, a1, b1) = (self.l, self.a, self.b);
+        let (l2, a2, b2) = (other.l, other.a, other.b);
+
+        let c1 = (a1 * a1 + b1 * b1).sqrt();
+        let c2 = (a2 * a2 + b2 * b2).sqrt();
+        let c_bar7 = ((c1 + c2) / 2.0).powi(7);
+        let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + POW25_7)).sqrt());
+
+        let a1p = a1 * (1.0 + g);
+        let a2p = a2 * (1.0 + g);
+        let c1p = (a1p * a1p + b1 * b1).sqrt();
+        let c2p = (a2p * a2p + b2 * b2).sqrt();
+
+        let hue = |b: f32, a: f32| {
+            if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).rem_euclid(2.0 * PI) }
+        };
+        let h1p = hue(b1, a1p);
+        let h2p = hue(b2, a2p);
+
+        let dlp = l2 - l1;
+        let dcp = c2p - c1p;
+        let dhp = if c1p * c2p == 0.0 {
+            0.0
+        } else if (h2p - h1p).abs() <= PI {
+            h2p - h1p
+        } else if h2p - h1p > PI {
+            h2p - h1p - 2.0 * PI
+        } else {
+            h2p - h1p + 2.0 * PI
+        };
+        let dhp = 2.0 * (c1p * c2p).sqrt() * (dhp / 2.0).sin();
+
+        let lp_bar = (l1 + l2) / 2.0;
+        let cp_bar = (c1p + c2p) / 2.0;
+        let hp_bar = if c1p * c2p == 0.0 {
+            h1p + h2p
+        } else if (h1p - h2p).abs() <= PI {
+            (h1p + h2p) / 2.0
+        } else if h1p + h2p < 2.0 * PI {
+            (h1p + h2p + 2.0 * PI) / 2.0
+        } else {
+            (h1p + h2p - 2.0 * PI) / 2.0
+        };
+
+        let t = 1.0
+            - 0.17 * (hp_bar - PI / 6.0).cos()
+            + 0.24 * (2.0 * hp_bar).cos()
+            + 0.32 * (3.0 * hp_bar + PI / 30.0).cos()
+            - 0.20 * (4.0 * hp_bar - 63.0 * PI / 180.0).cos();
+
+        let d_theta = PI / 6.0 * (-((hp_bar * 180.0 / PI - 275.0) / 25.0).powi(2)).exp();
+        let cp_bar7 = cp_bar.powi(7);
+        let r_c = 2.0 * (cp_bar7 / (cp_bar7 + POW25_7)).sqrt();
+        let lp50 = (lp_bar - 50.0).powi(2);
+        let s_l = 1.0 + 0.015 * lp50 / (20.0 + lp50).sqrt();
+        let s_c = 1.0 + 0.045 * cp_bar;
+        let s_h = 1.0 + 0.015 * cp_bar * t;
+        let r_t = -(2.0 * d_theta).sin() * r_c;
+
+        let dl = dlp / s_l;
+        let dc = dcp / s_c;
+        let dh = dhp / s_h;
+
+        (dl * dl + dc * dc + dh * dh + r_t * dc * dh).sqrt()
+    }
+}
+
+/// Metric used to decide how different two colors look
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorDistance {
+    /// Euclidean distance in RGB space
+    Rgb,
+    /// Euclidean distance in YUV space
+    Yuv,
+    /// CIE76 (euclidean distance in CIELAB space)
+    #[default]
+    #[value(name = "delta-e76")]
+    #[serde(rename = "delta-e76")]
+    DeltaE76,
+    /// CIEDE2000, perceptually more uniform but slower to compute
+    #[value(name = "delta-e2000")]
+    #[serde(rename = "delta-e2000")]
+    DeltaE2000,
+}
+
+impl ColorDistance {
+    /// Maps a color into the space this metric measures distances in
+    #[inline]
+    pub fn project(&self, c: &Color) -> [f32; 3] {
+        match self {
+            Self::Rgb => [c.r as f32, c.g as f32, c.b as f32],
+            Self::Yuv => {
+                let r = c.r as f32; let g = c.g as f32; let b = c.b as f32;
+                [
+                    r * 0.299    + g * 0.587   + b * 0.114,
+                    r * -0.14713 - g * 0.28886 + b * 0.436,
+                    r * 0.615    - g * 0.51499 - b * 0.10001,
+                ]
+            }
+            Self::DeltaE76 | Self::DeltaE2000 => {
+                let lab = c.to_cielab();
+                [lab.l, lab.a, lab.b]
+            }
+        }
+    }
+
+    /// Whether the metric is the euclidean distance between projected colors
+    #[inline]
+    pub fn is_euclidean(&self) -> bool {
+        !matches!(self, Self::DeltaE2000)
+    }
+
+    /// Distance between two colors returned by [`ColorDistance::project`]
+    #[inline]
+    pub fn distance_projected(&self, a: [f32; 3], b: [f32; 3]) -> f32 {
+        match self {
+            Self::DeltaE2000 => Lab::new(a[0], a[1], a[2]).delta_e2000(&Lab::new(b[0], b[1], b[2])),
+            _ => {
+                let d0 = a[0] - b[0]; let d1 = a[1] - b[1]; let d2 = a[2] - b[2];
+                (d0 * d0 + d1 * d1 + d2 * d2).sqrt()
+            }
+        }
+    }
+
+    pub fn distance(&self, a: &Color, b: &Color) -> f32 {
+        self.distance_projected(self.project(a), self.project(b))
+    }
+
+    /// Differences at or below this value are considered invisible
+    pub fn threshold(&self) -> f32 {
+        match self {
+            Self::Rgb | Self::Yuv => 2.0,
+            Self::DeltaE76 => 1.5,
+            Self::DeltaE2000 => 1.0,
+        }
     }
 }
 
@@ -56,5 +287,47 @@ mod tests {
     fn test_color_to_yuv(r: u8, g: u8, b: u8, y: u8, u: u8, v: u8) {
         let c = Color::new(r, g, b);
         assert_eq!(c.to_yuv(), (y, u, v));
-    }    
+    }
+
+    // reference values from http://www.brucelindbloom.com/index.html?ColorCalculator.html
+    #[case(255, 255, 255, 100.0, 0.0, 0.0)]
+    #[case(0, 0, 0, 0.0, 0.0, 0.0)]
+    #[case(255, 0, 0, 53.2408, 80.0925, 67.2032)]
+    #[case(0, 255, 0, 87.7347, -86.1827, 83.1793)]
+    #[case(0, 0, 255, 32.2970, 79.1875, -107.8602)]
+    #[case(128, 128, 128, 53.5850, 0.0, 0.0)]
+    fn test_color_to_cielab(r: u8, g: u8, b: u8, l: f32, a: f32, b_: f32) {
+        let lab = Color::new(r, g, b).to_cielab();
+        assert!((lab.l - l).abs() < 0.02, "L: {} != {}", lab.l, l);
+        assert!((lab.a - a).abs() < 0.02, "a: {} != {}", lab.a, a);
+        assert!((lab.b - b_).abs() < 0.02, "b: {} != {}", lab.b, b_);
+    }
+
+    // test data from Sharma, Wu & Dalal (2005), table 1
+    #[case((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425)]
+    #[case((50.0, 3.1571, -77.2803), (50.0, 0.0, -82.7485), 2.8615)]
+    #[case((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669)]
+    #[case((50.0, 2.49, -0.001), (50.0, -2.49, 0.0011), 7.2195)]
+    #[case((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492)]
+    #[case((60.2574, -34.0099, 36.2677), (60.4626, -34.1751, 39.4387), 1.2644)]
+    #[case((22.7233, 20.0904, -46.6940), (23.0331, 14.9730, -42.5619), 2.0373)]
+    #[case((2.0776, 0.0795, -1.1350), (0.9033, -0.0636, -0.5514), 0.9082)]
+    fn test_delta_e2000(a: (f32, f32, f32), b: (f32, f32, f32), expected: f32) {
+        let a = Lab::new(a.0, a.1, a.2);
+        let b = Lab::new(b.0, b.1, b.2);
+        assert!((a.delta_e2000(&b) - expected).abs() < 1e-3, "{} != {}", a.delta_e2000(&b), expected);
+        assert!((b.delta_e2000(&a) - expected).abs() < 1e-3);
+    }
+
+    #[case(ColorDistance::Rgb)]
+    #[case(ColorDistance::Yuv)]
+    #[case(ColorDistance::DeltaE76)]
+    #[case(ColorDistance::DeltaE2000)]
+    fn test_color_distance_saturated(distance: ColorDistance) {
+        let red = Color::new(255, 0, 0);
+        let blue = Color::new(0, 0, 255);
+        assert_eq!(distance.distance(&red, &red), 0.0);
+        assert!(distance.distance(&red, &blue) > distance.threshold());
+        assert!(distance.distance(&red, &Color::new(254, 0, 0)) <= distance.threshold());
+    }
 }
\ No newline at end of file
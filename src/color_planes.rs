@@ -0,0 +1,180 @@
+use rayon::prelude::*;
+
+use crate::{frame::Frame, Color, ColorDistance};
+
+/// Number of pixels processed per parallel task
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// A frame projected into the color space of a [`ColorDistance`], stored as one plane per channel
+/// so distances between frames can be computed with SIMD.
+#[derive(Debug, Clone)]
+pub struct ColorPlanes {
+    distance: ColorDistance,
+    planes: [Vec<f32>; 3],
+}
+
+impl ColorPlanes {
+    pub fn new(distance: ColorDistance) -> Self {
+        Self { distance, planes: Default::default() }
+    }
+
+    pub fn project(frame: &Frame, distance: ColorDistance) -> Self {
+        let mut planes = Self::new(distance);
+        planes.project_from(frame);
+        planes
+    }
+
+    /// Replaces the contents with the projection of `frame`, reusing the allocated planes
+    pub fn project_from(&mut self, frame: &Frame) {
+        match self.distance {
+            ColorDistance::Rgb => self.fill(frame, |c| ColorDistance::Rgb.project(c)),
+            ColorDistance::Yuv => self.fill(frame, |c| ColorDistance::Yuv.project(c)),
+            ColorDistance::DeltaE76 | ColorDistance::DeltaE2000 => self.fill(frame, |c| {
+                let lab = c.to_cielab();
+                [lab.l, lab.a, lab.b]
+            }),
+        }
+    }
+
+    #[inline(always)]
+    fn fill(&mut self, frame: &Frame, project: impl Fn(&Color) -> [f32; 3] + Sync) {
+        let data = frame.data();
+        for plane in &mut self.planes {
+            plane.resize(data.len(), 0.0);
+        }
+
+        let [p0, p1, p2] = &mut self.planes;
+        p0.par_chunks_mut(CHUNK_SIZE)
+            .zip(p1.par_chunks_mut(CHUNK_SIZE))
+            .zip(p2.par_chunks_mut(CHUNK_SIZE))
+            .zip(data.par_chunks(CHUNK_SIZE))
+            .for_each(|(((p0, p1), p2), colors)| {
+                for (i, c) in colors.iter().enumerate() {
+                    let [c0, c1, c2] = project(c);
+                    p0[i] = c0; p1[i] = c1; p2[i] = c2;
+                }
+            });
+    }
+
+    #[inline] pub fn len(&self) -> usize { self.planes[0].len() }
+    #[inline] pub fn is_empty(&self) -> bool { self.len() == 0 }
+    #[inline] pub fn distance(&self) -> ColorDistance { self.distance }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> [f32; 3] {
+        [self.planes[0][i], self.planes[1][i], self.planes[2][i]]
+    }
+    #[inline]
+    pub fn set(&mut self, i: usize, value: [f32; 3]) {
+        self.planes[0][i] = value[0];
+        self.planes[1][i] = value[1];
+        self.planes[2][i] = value[2];
+    }
+
+    /// Per-pixel difference scores between two frames. Scores increase monotonically with the
+    /// color distance; see [`ColorPlanes::score_threshold`].
+    pub fn scores(&self, other: &ColorPlanes) -> Vec<f32> {
+        let mut out = Vec::new();
+        self.scores_into(other, &mut out);
+        out
+    }
+
+    /// Like [`ColorPlanes::scores`], but reuses the allocation of `out`
+    pub fn scores_into(&self, other: &ColorPlanes, out: &mut Vec<f32>) {
+        assert_eq!(self.distance, other.distance, "planes use different color spaces");
+        assert_eq!(self.len(), other.len(), "planes have different sizes");
+
+        out.resize(self.len(), 0.0);
+        out.par_chunks_mut(CHUNK_SIZE)
+            .enumerate()
+            .for_each(|(c, out)| {
+                let range = c * CHUNK_SIZE..c * CHUNK_SIZE + out.len();
+                let a = [&self.planes[0][range.clone()], &self.planes[1][range.clone()], &self.planes[2][range.clone()]];
+                let b = [&other.planes[0][range.clone()], &other.planes[1][range.clone()], &other.planes[2][range]];
+
+                if self.distance.is_euclidean() {
+                    squared_distances(a, b, out);
+                } else {
+                    for (i, o) in out.iter_mut().enumerate() {
+                        *o = self.distance.distance_projected(
+                            [a[0][i], a[1][i], a[2][i]],
+                            [b[0][i], b[1][i], b[2][i]],
+                        );
+                    }
+                }
+            });
+    }
+
+    /// Scores above this value correspond to visible differences
+    pub fn score_threshold(&self) -> f32 {
+        let t = self.distance.threshold();
+        if self.distance.is_euclidean() { t * t } else { t }
+    }
+}
+
+/// Squared euclidean distances between the colors of two sets of planes
+pub fn squared_distances(a: [&[f32]; 3], b: [&[f32]; 3], out: &mut [f32]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx") {
+        // SAFETY: AVX support was checked above
+        unsafe { squared_distances_avx(a, b, out) };
+        return;
+    }
+    squared_distances_scalar(a, b, out, 0);
+}
+
+#[inline(always)]
+fn squared_distances_scalar(a: [&[f32]; 3], b: [&[f32]; 3], out: &mut [f32], start: usize) {
+    for i in start..out.len() {
+        let d0 = a[0][i] - b[0][i];
+        let d1 = a[1][i] - b[1][i];
+        let d2 = a[2][i] - b[2][i];
+        out[i] = d0 * d0 + d1 * d1 + d2 * d2;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn squared_distances_avx(a: [&[f32]; 3], b: [&[f32]; 3], out: &mut [f32]) {
+    use std::arch::x86_64::*;
+
+    let len = out.len();
+    assert!(a.iter().chain(b.iter()).all(|p| p.len() >= len));
+
+    let mut i = 0;
+    while i + 8 <= len {
+        let d0 = _mm256_sub_ps(_mm256_loadu_ps(a[0].as_ptr().add(i)), _mm256_loadu_ps(b[0].as_ptr().add(i)));
+        let d1 = _mm256_sub_ps(_mm256_loadu_ps(a[1].as_ptr().add(i)), _mm256_loadu_ps(b[1].as_ptr().add(i)));
+        let d2 = _mm256_sub_ps(_mm256_loadu_ps(a[2].as_ptr().add(i)), _mm256_loadu_ps(b[2].as_ptr().add(i)));
+        let sum = _mm256_add_ps(
+            _mm256_add_ps(_mm256_mul_ps(d0, d0), _mm256_mul_ps(d1, d1)),
+            _mm256_mul_ps(d2, d2),
+        );
+        _mm256_storeu_ps(out.as_mut_ptr().add(i), sum);
+        i += 8;
+    }
+    squared_distances_scalar(a, b, out, i);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_match_distance() {
+        let colors = (0..1000)
+            .map(|i| Color::new((i * 7) as u8, (i * 13) as u8, (i * 29) as u8))
+            .collect::<Vec<_>>();
+        let a = Frame::new(100, 10, colors.clone());
+        let b = Frame::new(100, 10, colors.into_iter().rev().collect::<Vec<_>>());
+
+        for distance in [ColorDistance::Rgb, ColorDistance::Yuv, ColorDistance::DeltaE76, ColorDistance::DeltaE2000] {
+            let scores = ColorPlanes::project(&a, distance).scores(&ColorPlanes::project(&b, distance));
+            for (i, score) in scores.iter().enumerate() {
+                let d = distance.distance(&a.data()[i], &b.data()[i]);
+                let expected = if distance.is_euclidean() { d * d } else { d };
+                assert!((score - expected).abs() <= expected * 1e-4 + 1e-3, "{:?} @ {}", distance, i);
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame::{Frame, FrameFile},
+    CacheKey, Color, Error, FrameCache, Result, TextRenderer, Transition, VideoFilters,
+};
+
+pub const DEFAULT_CHROMA_TOLERANCE: f32 = 48.0;
+
+/// A video or image drawn on top of the main video
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    pub input: String,
+    /// Position of the top left corner of the layer in the main video (in px)
+    #[serde(default)]
+    pub x: usize,
+    #[serde(default)]
+    pub y: usize,
+    /// Size the layer is scaled to, -1 keeps the aspect ratio [default: same as source]
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// Layers with a higher z are drawn on top of layers with a lower z. The main video is
+    /// always at the bottom.
+    #[serde(default)]
+    pub z: i32,
+    /// Pixels of this color are transparent
+    pub chroma_key: Option<Color>,
+    /// Maximum RGB distance to the chroma key of a transparent pixel [default: 48]
+    pub chroma_tolerance: Option<f32>,
+    /// Start over when the layer is shorter than the main video, instead of hiding it
+    #[serde(default = "default_repeat")]
+    pub repeat: bool,
+}
+
+fn default_repeat() -> bool {
+    true
+}
+
+struct CachedLayer {
+    layer: Layer,
+    cache: FrameCache,
+    frame_count: usize,
+}
+
+impl CachedLayer {
+    /// Frame of the layer shown on frame `idx` of the main video, if any
+    fn frame_idx(&self, idx: usize) -> Option<usize> {
+        match (self.frame_count, self.layer.repeat) {
+            (0, _) => None,
+            (n, true) => Some((idx - 1) % n + 1),
+            (n, false) => (idx <= n).then_some(idx),
+        }
+    }
+}
+
+/// Transition from the previous playlist entry, drawn over the first frames of the video
+struct IncomingTransition {
+    transition: Transition,
+    frames: usize,
+    /// Last frame of the previous entry, in the coordinates of the video
+    from: Frame,
+}
+
+/// Loads frames of the main video and draws the layers on top, in z-order, followed by the text
+pub struct Compositor {
+    base: FrameCache,
+    layers: Vec<CachedLayer>,
+    transition: Option<IncomingTransition>,
+    text: Option<TextRenderer>,
+}
+
+impl Compositor {
+    /// Extracts the frames of every layer (at the frame-rate of the main video, whose frames are
+    /// in `base`), unless they are already cached
+    pub async fn prepare(
+        base: FrameCache,
+        layers: Vec<Layer>,
+        fps: f64,
+        force: bool,
+        progress: bool,
+    ) -> Result<Self> {
+        if !layers.is_empty() {
+            let first = FrameFile::in_cache(&base, 1).load()?;
+            if let Some(layer) = layers.iter().find(|l| l.x >= first.width() || l.y >= first.height()) {
+                return Err(Error::InvalidConfig(format!(
+                    "Layer {} at {},{} lies outside the {}x{} video",
+                    layer.input, layer.x, layer.y, first.width(), first.height()
+                )));
+            }
+        }
+
+        let mut cached = Vec::with_capacity(layers.len());
+        for layer in layers {
+            let width = layer.width.unwrap_or(-1);
+            let height = layer.height.unwrap_or(-1);
+            let key = CacheKey::new(layer.input.clone(), width, height, fps);
+            let cache = FrameCache::input(&key);
+            let metadata = cache
+                .prepare(&key, &layer.input, Some(fps), width, height, &VideoFilters::default(), force, progress)
+                .await?;
+            cached.push(CachedLayer { layer, cache, frame_count: metadata.frame_count });
+        }
+        // stable, so layers with the same z keep the order they were given in
+        cached.sort_by_key(|l| l.layer.z);
+
+        Ok(Self { base, layers: cached, transition: None, text: None })
+    }
+
+    /// Blends the first `frames` frames with `from` (the last frame of the previous playlist
+    /// entry, in the coordinates of this video)
+    pub fn with_transition(mut self, transition: Transition, frames: usize, from: Frame) -> Self {
+        self.transition = (transition != Transition::Cut && frames > 0)
+            .then_some(IncomingTransition { transition, frames, from });
+        self
+    }
+
+    /// Draws text overlays and subtitles on top of everything else
+    pub fn with_text(mut self, text: TextRenderer) -> Self {
+        self.text = Some(text);
+        self
+    }
+
+    /// Cache holding the frames of the main video
+    #[inline] pub fn base(&self) -> &FrameCache { &self.base }
+
+    /// Loads frame `idx` of the main video with all layers drawn on top
+    pub fn load_frame(&self, idx: usize) -> Result<Frame> {
+        let mut frame = FrameFile::in_cache(&self.base, idx).load()?;
+
+        for cached in &self.layers {
+            let Some(layer_idx) = cached.frame_idx(idx) else { continue };
+            let layer_frame = FrameFile::in_cache(&cached.cache, layer_idx).load().map_err(|e| {
+                Error::Custom(format!("Failed to load frame {} of layer {}: {}", layer_idx, cached.layer.input, e))
+            })?;
+            draw_layer(&mut frame, &layer_frame, &cached.layer);
+        }
+
+        if let Some(t) = self.transition.as_ref().filter(|t| idx <= t.frames) {
+            t.transition.apply(&t.from, &mut frame, idx as f32 / (t.frames + 1) as f32);
+        }
+        if let Some(text) = &self.text {
+            text.draw(&mut frame, idx);
+        }
+        Ok(frame)
+    }
+}
+
+/// Draws `layer_frame` onto `frame` at the position of `layer`, skipping pixels that match the
+/// chroma key. Parts of the layer outside the frame are cut off.
+pub fn draw_layer(frame: &mut Frame, layer_frame: &Frame, layer: &Layer) {
+    let width = layer_frame.width().min(frame.width().saturating_sub(layer.x));
+    let height = layer_frame.height().min(frame.height().saturating_sub(layer.y));
+    let tolerance = layer.chroma_tolerance.unwrap_or(DEFAULT_CHROMA_TOLERANCE);
+    let frame_width = frame.width();
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    for row in 0..height {
+        let src = &layer_frame.data()[row * layer_frame.width()..][..width];
+        let start = (layer.y + row) * frame_width + layer.x;
+        let dst = &mut frame.data_mut()[start..start + width];
+
+        match &layer.chroma_key {
+            None => dst.copy_from_slice(src),
+            Some(key) => {
+                for (d, s) in dst.iter_mut().zip(src) {
+                    if rgb_distance(s, key) > tolerance {
+                        *d = *s;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+fn rgb_distance(a: &Color, b: &Color) -> f32 {
+    let dr = a.r as f32 - b.r as f32;
+    let dg = a.g as f32 - b.g as f32;
+    let db = a.b as f32 - b.b as f32;
+    (dr * dr + dg * dg + db * db).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(x: usize, y: usize, chroma_key: Option<Color>) -> Layer {
+        Layer {
+            input: String::new(),
+            x,
+            y,
+            width: None,
+            height: None,
+            z: 0,
+            chroma_key,
+            chroma_tolerance: None,
+            repeat: true,
+        }
+    }
+
+    #[test]
+    fn test_draw_layer_clips_and_keys() {
+        let black = Color::new(0, 0, 0);
+        let green = Color::new(0, 255, 0);
+        let red = Color::new(255, 0, 0);
+
+        let mut frame = Frame::new(4, 3, vec![black; 12]);
+        // 2x2 layer hanging over the bottom right corner, with one green pixel
+        let overlay = Frame::new(2, 2, vec![red, Color::new(10, 250, 5), red, red]);
+        draw_layer(&mut frame, &overlay, &layer(3, 1, Some(green)));
+
+        let mut expected = [black; 12];
+        expected[4 + 3] = red;
+        expected[8 + 3] = red;
+        assert_eq!(frame.data(), &expected[..]);
+
+        // without a chroma key the green pixel is drawn too
+        draw_layer(&mut frame, &overlay, &layer(0, 0, None));
+        assert_eq!(frame.data()[1], Color::new(10, 250, 5));
+    }
+
+    #[test]
+    fn test_draw_layer_outside_frame() {
+        let black = Color::new(0, 0, 0);
+        let mut frame = Frame::new(10, 10, vec![black; 100]);
+        let overlay = Frame::new(2, 2, vec![Color::new(255, 0, 0); 4]);
+        draw_layer(&mut frame, &overlay, &layer(20, 9, None));
+        draw_layer(&mut frame, &overlay, &layer(0, 10, None));
+        assert!(frame.data().iter().all(|c| *c == black));
+    }
+}
@@ -2,16 +2,55 @@ use serde::{Serialize, Deserialize};
 
 mod v1;
 mod v2;
+mod mono;
+
+use std::borrow::Cow;
 
 use v1::VideoCompressorV1;
 use v2::VideoCompressorV2;
+use mono::VideoCompressorMono;
 
 use clap::ValueEnum;
 
 use crate::{
-    args::CompressionLevelArg, frame::{Frame,FrameData}, Result
+    args::CompressionLevelArg, frame::{Frame,FrameData}, Args, Color, ColorDistance, Result
 };
 
+/// Settings shared by all compression algorithms
+#[derive(Debug, Clone)]
+pub struct CompressorOptions {
+    pub debug: bool,
+    pub color_distance: ColorDistance,
+    pub mono_threshold: u8,
+    pub mono_fg: Color,
+    pub mono_bg: Color,
+}
+
+impl Default for CompressorOptions {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            color_distance: ColorDistance::default(),
+            mono_threshold: 128,
+            mono_fg: Color::new(255, 255, 255),
+            mono_bg: Color::new(0, 0, 0),
+        }
+    }
+}
+
+impl From<&Args> for CompressorOptions {
+    fn from(args: &Args) -> Self {
+        let default = Self::default();
+        Self {
+            debug: args.debug,
+            color_distance: args.color_distance,
+            mono_threshold: args.mono_threshold.unwrap_or(default.mono_threshold),
+            mono_fg: args.mono_fg.unwrap_or(default.mono_fg),
+            mono_bg: args.mono_bg.unwrap_or(default.mono_bg),
+        }
+    }
+}
+
 macro_rules! impl_video_compressor {
     { $($name:ident, $t:ty);*; } => {
         #[derive(Clone)]
@@ -26,9 +65,9 @@ macro_rules! impl_video_compressor {
         }
 
         impl VideoCompressor {
-            pub fn new(alg: CompressionAlgConfig, level: CompressionLevelArg, debug: bool) -> Result<Self> {        
+            pub fn new(alg: CompressionAlgConfig, level: CompressionLevelArg, options: &CompressorOptions) -> Result<Self> {
                 match alg {
-                    $(CompressionAlgConfig::$name => Ok(Self::$name(<$t>::new(level, debug)?)),)*
+                    $(CompressionAlgConfig::$name => Ok(Self::$name(<$t>::new(level, options)?)),)*
                 }
             }
 
@@ -37,6 +76,13 @@ macro_rules! impl_video_compressor {
                     $(Self::$name(c) => c.compress_frame(new_frame)),*
                 }
             }
+
+            /// Reconstructed canvas, i.e. what the wall shows after the last compressed frame
+            pub fn canvas(&self) -> Option<Cow<'_, Frame>> {
+                match self {
+                    $(Self::$name(c) => c.canvas()),*
+                }
+            }
         }
     };   
 }
@@ -44,8 +90,10 @@ macro_rules! impl_video_compressor {
 impl_video_compressor! { 
     V1, VideoCompressorV1; 
     V2, VideoCompressorV2; 
+    Mono, VideoCompressorMono;
 }
 
+#[allow(clippy::derivable_impls)] // variants are generated by the macro above
 impl Default for CompressionAlgConfig {
     fn default() -> Self {
         Self::V2
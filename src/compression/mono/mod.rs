@@ -0,0 +1,115 @@
+use std::borrow::Cow;
+
+use crate::{
+    args::CompressionLevelArg,
+    frame::{Frame, FrameData},
+    BitFrame, Color, CompressorOptions, Error, Pixel, Result,
+};
+
+/// 1-bit compressor for black-and-white content. Frames are thresholded to a foreground and
+/// background color and diffed with a word-level XOR.
+#[derive(Clone)]
+pub struct VideoCompressorMono {
+    last_frame: Option<BitFrame>,
+    level: CompressionLevelMono,
+    threshold: u8,
+    fg: Color,
+    bg: Color,
+    /// Word to start the next diff at, so a limited pixel budget doesn't starve the bottom rows
+    cursor: usize,
+    debug: bool,
+}
+
+impl VideoCompressorMono {
+    pub fn new(level: CompressionLevelArg, options: &CompressorOptions) -> Result<Self> {
+        Ok(Self {
+            last_frame: None,
+            level: level.try_into()?,
+            threshold: options.mono_threshold,
+            fg: options.mono_fg,
+            bg: options.mono_bg,
+            cursor: 0,
+            debug: options.debug,
+        })
+    }
+
+    fn delta(&mut self, new: &BitFrame) -> FrameData {
+        let Some(old) = &mut self.last_frame else {
+            unreachable!("delta() called without a previous frame")
+        };
+
+        let limit = match self.level.target_pixels_per_frame {
+            0 => usize::MAX,
+            n => n,
+        };
+
+        let changed = old.diff(new, self.cursor).take(limit).collect::<Vec<_>>();
+        if changed.is_empty() {
+            return FrameData::Empty;
+        }
+        if changed.len() == limit {
+            self.cursor = changed[limit - 1] / 64;
+        }
+
+        let data = changed
+            .into_iter()
+            .map(|i| {
+                let on = new.get(i);
+                old.set(i, on);
+                Pixel::at_index(i, new.width(), if on { self.fg } else { self.bg })
+            })
+            .collect();
+
+        FrameData::Delta(data)
+    }
+
+    pub fn canvas(&self) -> Option<Cow<'_, Frame>> {
+        self.last_frame
+            .as_ref()
+            .map(|f| Cow::Owned(f.to_frame(self.fg, self.bg)))
+    }
+
+    pub fn compress_frame(&mut self, new_frame: &Frame) -> FrameData {
+        let bits = BitFrame::threshold(new_frame, self.threshold);
+
+        match &self.last_frame {
+            Some(lf) if lf.width() == bits.width() && lf.height() == bits.height() => {
+                let data = self.delta(&bits);
+
+                if self.debug {
+                    let debug_frame = Frame::debug(new_frame.width(), new_frame.height());
+                    let debug_frame = debug_frame.apply_frame_data(&data);
+                    debug_frame.to_full_frame_data()
+                } else {
+                    data
+                }
+            }
+            _ => {
+                let data = bits.to_frame(self.fg, self.bg).to_full_frame_data();
+                self.last_frame = Some(bits);
+                self.cursor = 0;
+                data
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////
+#[derive(Clone)]
+pub struct CompressionLevelMono {
+    target_pixels_per_frame: usize,
+}
+
+impl TryFrom<CompressionLevelArg> for CompressionLevelMono {
+    type Error = Error;
+
+    fn try_from(arg: CompressionLevelArg) -> Result<Self> {
+        match arg {
+            CompressionLevelArg::None => Ok(Self { target_pixels_per_frame: 0 }),
+            CompressionLevelArg::Number(n) => Ok(Self { target_pixels_per_frame: n }),
+            _ => Err(Error::InvalidArgs(
+                "Invalid compression level for mono".to_string(),
+            )),
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use std::borrow::Cow;
+
 use rayon::prelude::*;
 
 use crate::{
-    args::CompressionLevelArg, frame::{Frame,FrameData}, Result, Error, Pixel
+    args::CompressionLevelArg, frame::{Frame,FrameData}, CompressorOptions, Result, Error, Pixel
 };
 
 #[derive(Clone)]
@@ -12,11 +14,11 @@ pub struct VideoCompressorV1 {
 }
 
 impl VideoCompressorV1 {
-    pub fn new(level: CompressionLevelArg, debug: bool) -> Result<Self> {
+    pub fn new(level: CompressionLevelArg, options: &CompressorOptions) -> Result<Self> {
         Ok(Self { 
             last_frame: None, 
             level: level.try_into()?,
-            debug 
+            debug: options.debug,
         })
     }
     fn delta(&self, old: &Frame, new: &Frame) -> FrameData {                
@@ -33,27 +35,26 @@ impl VideoCompressorV1 {
 
                 if y_diff > self.level.luminance_treshold()
                 || c_diff > self.level.chroma_threshold(old_y) {
-                    let x = i % old.width();
-                    let y = i / old.width();
-                    
-                    Some(Pixel { x, y, color: *new_val })
+                    Some(Pixel::at_index(i, old.width(), *new_val))
                 } else {
                     None
                 }
             })
             .collect();
     
-        if px_vec.len() == 0 {
+        if px_vec.is_empty() {
             FrameData::Empty
         } else {
             FrameData::Delta(px_vec)
         }
     }
 
+    #[inline] pub fn canvas(&self) -> Option<Cow<'_, Frame>> { self.last_frame.as_ref().map(Cow::Borrowed) }
+
     pub fn compress_frame(&mut self, new_frame: &Frame) -> FrameData {        
-        let frame_data = match &self.last_frame {
+        match &self.last_frame {
             Some(lf) => {
-                let data = self.delta(&lf, &new_frame);                
+                let data = self.delta(lf, new_frame);                
 
                 self.last_frame = Some(lf.apply_frame_data(&data));
 
@@ -71,8 +72,7 @@ impl VideoCompressorV1 {
                 self.last_frame = Some(new_frame.clone());
                 data
             }
-        };
-        frame_data
+        }
     }
 }
 
@@ -1,83 +1,92 @@
+use std::borrow::Cow;
+
 use rayon::prelude::*;
 
 use crate::{
     args::CompressionLevelArg,
     frame::{Frame, FrameData},
-    Error, Pixel, Result,
+    ColorDistance, ColorPlanes, CompressorOptions, Error, Pixel, Result,
 };
 
 #[derive(Clone)]
 pub struct VideoCompressorV2 {
     last_frame: Option<Frame>,
+    /// `last_frame` projected into the color space of `distance`, so only new frames need converting
+    last_planes: Option<ColorPlanes>,
+    /// Buffers reused between frames
+    new_planes: ColorPlanes,
+    scores: Vec<f32>,
     level: CompressionLevelV2,
+    distance: ColorDistance,
     debug: bool,
 }
 
 impl VideoCompressorV2 {
-    pub fn new(level: CompressionLevelArg, debug: bool) -> Result<Self> {
+    pub fn new(level: CompressionLevelArg, options: &CompressorOptions) -> Result<Self> {
         Ok(Self {
             last_frame: None,
+            last_planes: None,
+            new_planes: ColorPlanes::new(options.color_distance),
+            scores: Vec::new(),
             level: level.try_into()?,                
-            debug,
+            distance: options.color_distance,
+            debug: options.debug,
         })
     }
 
-    fn delta(&self, old: &Frame, new: &Frame) -> FrameData {
-        let mut priorities = old
-            .data()
-            .into_par_iter()
-            .zip(new.data().into_par_iter())
+    fn delta(
+        old_planes: &mut ColorPlanes,
+        new_planes: &mut ColorPlanes,
+        scores: &mut Vec<f32>,
+        level: &CompressionLevelV2,
+        new: &Frame,
+    ) -> FrameData {
+        new_planes.project_from(new);
+        old_planes.scores_into(new_planes, scores);
+        let threshold = old_planes.score_threshold();
+
+        let mut priorities = scores
+            .par_iter()
+            .copied()
             .enumerate()
-            .flat_map(|(i, (old_val, new_val))| {
-                let x = i % old.width();
-                let y = i / old.width();
-
-                // temporal chroma subsampling
-                let (old_y, old_u, old_v) = old_val.to_cielab();
-                let (new_y, new_u, new_v) = new_val.to_cielab();
-
-                // euclidean distance
-                let diff = (old_y as i32 - new_y as i32).pow(2) as usize
-                    + (old_u as i32 - new_u as i32).pow(2) as usize
-                    + (old_v as i32 - new_v as i32).pow(2) as usize;
-
-                if diff > 2 {
-                    Some((
-                        diff,
-                        Pixel {
-                            x,
-                            y,
-                            color: *new_val,
-                        },
-                    ))
-                } else {
-                    None
-                }
-            })
+            .filter(|(_, score)| *score > threshold)
+            .map(|(i, score)| (score, i))
             .collect::<Vec<_>>();
 
-        if priorities.len() == 0 {
-            FrameData::Empty
-        } else {          
-            let data = match self.level.target_pixels_per_frame {
-                0 => priorities.into_iter().map(|(_, p)| p).collect(),
-                n => {
-                    priorities.sort_unstable_by(|a, b| b.0.cmp(&a.0));
-                    priorities
-                        .into_iter()
-                        .take(n)
-                        .map(|(_, p)| p)
-                        .collect()
-                }
-            };
-            FrameData::Delta(data)
+        if priorities.is_empty() {
+            return FrameData::Empty;
         }
+
+        if level.target_pixels_per_frame != 0 {
+            let n = level.target_pixels_per_frame.min(priorities.len());
+            priorities.select_nth_unstable_by(n - 1, |a, b| b.0.total_cmp(&a.0));
+            priorities.truncate(n);
+            priorities.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        }
+
+        let data = priorities
+            .into_iter()
+            .map(|(_, i)| {
+                old_planes.set(i, new_planes.get(i));
+                Pixel::at_index(i, new.width(), new.data()[i])
+            })
+            .collect();
+
+        FrameData::Delta(data)
     }
 
+    #[inline] pub fn canvas(&self) -> Option<Cow<'_, Frame>> { self.last_frame.as_ref().map(Cow::Borrowed) }
+
     pub fn compress_frame(&mut self, new_frame: &Frame) -> FrameData {
-        let frame_data = match &self.last_frame {
-            Some(lf) => {
-                let data = self.delta(&lf, &new_frame);
+        match (&self.last_frame, &mut self.last_planes) {
+            (Some(lf), Some(planes)) => {
+                let data = Self::delta(
+                    planes,
+                    &mut self.new_planes,
+                    &mut self.scores,
+                    &self.level,
+                    new_frame,
+                );
 
                 self.last_frame = Some(lf.apply_frame_data(&data));
 
@@ -89,13 +98,13 @@ impl VideoCompressorV2 {
                     data
                 }
             }
-            None => {
+            _ => {
                 let data = new_frame.to_full_frame_data();
                 self.last_frame = Some(new_frame.clone());
+                self.last_planes = Some(ColorPlanes::project(new_frame, self.distance));
                 data
             }
-        };
-        frame_data
+        }
     }
 }
 
@@ -9,7 +9,20 @@ pub struct Config {
     pub args: Args,
     #[serde(default)]
     #[serde(skip_serializing_if = "HashMap::is_empty")]
-    pub targets: HashMap<String, Target>
+    pub targets: HashMap<String, Target>,
+    /// Named lists of targets, usable wherever a target is expected
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub groups: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub walls: HashMap<String, Wall>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub layers: HashMap<String, Layer>,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub texts: HashMap<String, TextOverlay>,
 }
 
 #[derive(Hash, Clone, Debug, Serialize, Deserialize)]
@@ -18,6 +31,14 @@ pub struct Target {
     pub protocol: Protocol,
     #[serde(default)]
     pub canvas: u8,
+    /// Offset added to `x_offset`/`y_offset` on this target
+    #[serde(default)]
+    pub x_offset: usize,
+    #[serde(default)]
+    pub y_offset: usize,
+    /// Rotation, mirroring and scaling of the pixels sent to this target
+    #[serde(default, flatten)]
+    pub transform: OutputTransform,
 }
 
 impl Config {
@@ -54,7 +75,7 @@ impl Config {
                 
             },
         };
-        Ok(toml::from_str(&config).map_err(|e| Error::FileParseError(e.to_string()))?)
+        toml::from_str(&config).map_err(|e| Error::FileParseError(e.to_string()))
     }
 }
 
@@ -63,6 +84,10 @@ impl Default for Config {
         Self {
             args: Args::config_default(),
             targets: HashMap::new(),
+            groups: HashMap::new(),
+            walls: HashMap::new(),
+            layers: HashMap::new(),
+            texts: HashMap::new(),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,223 @@
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Command sent to the control socket, one JSON object per line, e.g. `{"command": "seek", "frame": 100}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ControlCommand {
+    Pause,
+    Resume,
+    /// Continue playback at `frame` (1-based)
+    Seek { frame: usize },
+    /// Move the video on the canvas
+    Offset { x: usize, y: usize },
+    /// Change the compression level, see `--compression-level`
+    CompressionLevel { level: String },
+    /// Play another input file instead
+    Input { input: String },
+    Status,
+}
+
+/// State of playback, returned by [`ControlCommand::Status`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlaybackStatus {
+    pub input: String,
+    /// Last frame sent (1-based)
+    pub frame: usize,
+    pub frame_count: usize,
+    pub fps: f64,
+    pub paused: bool,
+    pub x_offset: usize,
+    pub y_offset: usize,
+    pub compression_level: String,
+    pub jit: bool,
+}
+
+/// Reply to a command, one JSON object per line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<PlaybackStatus>,
+}
+
+impl ControlResponse {
+    pub fn ok() -> Self {
+        Self { ok: true, error: None, status: None }
+    }
+    pub fn error(e: impl ToString) -> Self {
+        Self { ok: false, error: Some(e.to_string()), status: None }
+    }
+    pub fn status(status: PlaybackStatus) -> Self {
+        Self { ok: true, error: None, status: Some(status) }
+    }
+}
+
+/// A command waiting to be handled by the playback loop
+pub struct ControlRequest {
+    pub command: ControlCommand,
+    reply: Sender<ControlResponse>,
+}
+
+impl ControlRequest {
+    pub fn reply(self, response: ControlResponse) {
+        // the client may have disconnected in the meantime
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Local control interface: a Unix socket taking line-based JSON commands. Commands are queued
+/// until the playback loop picks them up, between two frames.
+pub struct ControlServer {
+    path: PathBuf,
+    requests: Mutex<Receiver<ControlRequest>>,
+}
+
+impl ControlServer {
+    /// Listens on a Unix socket at `path`, replacing a stale socket left behind by an earlier run.
+    /// Anything else at `path` is left alone.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::UnixListener;
+
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(Error::InvalidArgs(format!(
+                    "{} already exists and is not a socket", path.display()
+                )));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::Io(e)),
+        }
+        let listener = UnixListener::bind(path).map_err(|e| {
+            Error::Custom(format!("Failed to bind control socket {}: {}", path.display(), e))
+        })?;
+
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Ok(reader) = stream.try_clone() else { continue };
+                let tx = tx.clone();
+                thread::spawn(move || serve(BufReader::new(reader), stream, tx));
+            }
+        });
+
+        Ok(Self { path: path.to_path_buf(), requests: Mutex::new(rx) })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path) -> Result<Self> {
+        Err(Error::InvalidArgs("--control is only supported on Unix".to_string()))
+    }
+
+    /// Next queued command, if any
+    pub fn try_recv(&self) -> Option<ControlRequest> {
+        self.requests.lock().unwrap().try_recv().ok()
+    }
+
+    /// Waits for the next command
+    pub fn recv(&self) -> Option<ControlRequest> {
+        self.requests.lock().unwrap().recv().ok()
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Handles the commands of one client, until it disconnects
+fn serve(reader: impl BufRead, mut writer: impl Write, requests: Sender<ControlRequest>) {
+    for line in reader.lines() {
+        let Ok(line) = line else { return };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ControlCommand>(&line) {
+            Ok(command) => {
+                let (reply, response) = channel();
+                if requests.send(ControlRequest { command, reply }).is_err() {
+                    return;
+                }
+                response
+                    .recv()
+                    .unwrap_or_else(|_| ControlResponse::error("playback stopped"))
+            }
+            Err(e) => ControlResponse::error(format!("invalid command: {}", e)),
+        };
+
+        let mut out = serde_json::to_string(&response).expect("Failed to serialize response");
+        out.push('\n');
+        if writer.write_all(out.as_bytes()).and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_commands() {
+        let parse = |s| serde_json::from_str::<ControlCommand>(s).unwrap();
+        assert_eq!(parse(r#"{"command": "pause"}"#), ControlCommand::Pause);
+        assert_eq!(parse(r#"{"command": "seek", "frame": 12}"#), ControlCommand::Seek { frame: 12 });
+        assert_eq!(
+            parse(r#"{"command": "compression-level", "level": "high"}"#),
+            ControlCommand::CompressionLevel { level: "high".to_string() }
+        );
+        assert!(serde_json::from_str::<ControlCommand>(r#"{"command": "rewind"}"#).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_roundtrip() {
+        use std::os::unix::net::UnixStream;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let server = ControlServer::bind(&path).unwrap();
+
+        let handle = thread::spawn(move || {
+            let request = server.recv().unwrap();
+            assert_eq!(request.command, ControlCommand::Resume);
+            request.reply(ControlResponse::ok());
+        });
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"{\"command\": \"resume\"}\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(&client).read_line(&mut line).unwrap();
+        assert_eq!(line, "{\"ok\":true}\n");
+        handle.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bind_only_replaces_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("video.mp4");
+        std::fs::write(&file, b"not a socket").unwrap();
+        assert!(ControlServer::bind(&file).is_err());
+        assert_eq!(std::fs::read(&file).unwrap(), b"not a socket");
+
+        // a socket left behind by an earlier run
+        let stale = dir.path().join("control.sock");
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        assert!(ControlServer::bind(&stale).is_ok());
+    }
+}
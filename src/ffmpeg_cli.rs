@@ -1,7 +1,9 @@
 use std::process::Stdio;
 use colored::Colorize;
+use std::path::Path;
 use tokio::{process::Command, io::{BufReader, AsyncBufReadExt}};
-use crate::{Result, Error, paths, VideoMetadata};
+use std::io::Write;
+use crate::{frame::Frame, Result, Error, VideoMetadata, AUDIO_CHANNELS, AUDIO_SAMPLE_RATE, VideoFilters};
 
 pub async fn get_video_framerate(input: &str) -> Result<f64> {
     
@@ -27,20 +29,33 @@ pub async fn get_video_framerate(input: &str) -> Result<f64> {
     let numerator = iter.next()
         .unwrap() // unreachable
         .parse::<i32>()
-        .expect(&format!("'{first_line}' is not a valid framerate"));
+        .unwrap_or_else(|_| panic!("'{first_line}' is not a valid framerate"));
     
     let denominator = iter.next()
         .expect("Missing denominator")
         .parse::<i32>()
-        .expect(&format!(""));
+        .unwrap_or_else(|_| panic!("'{first_line}' is not a valid framerate"));
 
     Ok(numerator as f64 / denominator as f64)
 }
 
-pub async fn extract_video_frames(input: &str, fps: f64, width: i32, height: i32) -> Result<VideoMetadata> {
-    println!("{} Extracting frames to {} ...", "::".blue(), paths::cache_frames().to_str().unwrap());
+/// Extracts the frames of `input` into `dir` (as `frame1.ppm`, `frame2.ppm`, ...), applying
+/// `filters`. Without `progress`, nothing is printed, e.g. when extracting in the background
+/// during playback.
+pub async fn extract_video_frames(
+    input: &str,
+    fps: f64,
+    width: i32,
+    height: i32,
+    filters: &VideoFilters,
+    dir: &Path,
+    progress: bool,
+) -> Result<VideoMetadata> {
+    if progress {
+        println!("{} Extracting frames to {} ...", "::".blue(), dir.to_str().unwrap());
+    }
     
-    if let Err(e) = std::fs::create_dir_all(paths::cache_frames()) {
+    if let Err(e) = std::fs::create_dir_all(dir) {
         match e.kind() {
             std::io::ErrorKind::AlreadyExists => {},
             _ => return Err(Error::Io(e))
@@ -54,10 +69,10 @@ pub async fn extract_video_frames(input: &str, fps: f64, width: i32, height: i32
         .arg("-i")
         .arg(input)
         .arg("-vf")
-        .arg(format!("fps={fps},scale={width}:{height}"))
+        .arg(filters.chain(fps, width, height))
         .arg("-progress").arg("-").arg("-nostats") // black magic
         
-        .arg(format!("{}/frame%d.ppm", paths::cache_frames().to_str().unwrap()))
+        .arg(format!("{}/frame%d.ppm", dir.to_str().unwrap()))
         .stdout(Stdio::piped())     
         .stderr(Stdio::piped())                   
         .spawn()
@@ -92,22 +107,26 @@ pub async fn extract_video_frames(input: &str, fps: f64, width: i32, height: i32
         }
     });
 
-    let cursorpos = crossterm::cursor::position().unwrap();
+    if progress {
+        let cursorpos = crossterm::cursor::position().unwrap();
 
-    let print_over_line = |str: &str| {
-        crossterm::execute!(std::io::stdout(), crossterm::cursor::MoveTo(0, cursorpos.1)).unwrap();
-        // clear
-        crossterm::execute!(std::io::stdout(), crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine)).unwrap();
-        crossterm::execute!(std::io::stdout(), crossterm::style::Print(str)).unwrap();            
-    };
+        let print_over_line = |str: &str| {
+            crossterm::execute!(std::io::stdout(), crossterm::cursor::MoveTo(0, cursorpos.1)).unwrap();
+            // clear
+            crossterm::execute!(std::io::stdout(), crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine)).unwrap();
+            crossterm::execute!(std::io::stdout(), crossterm::style::Print(str)).unwrap();            
+        };
 
-    while !handle.is_finished() {
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        while !handle.is_finished() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
-        let count = counter.load(std::sync::atomic::Ordering::Relaxed);
-        print_over_line(&format!("{} frames extracted", count));
+            let count = counter.load(std::sync::atomic::Ordering::Relaxed);
+            print_over_line(&format!("{} frames extracted", count));
+        }
+        println!("\nDone.");
+    } else {
+        let _ = handle.await;
     }
-    println!("\nDone.");
 
     let _ = cmd.wait().await
         .map_err(|e| Error::FFmpegError(
@@ -115,7 +134,115 @@ pub async fn extract_video_frames(input: &str, fps: f64, width: i32, height: i32
         ))?;
     
     
-    let frame_count = std::fs::read_dir(paths::cache_frames())?.count();
+    let frame_count = std::fs::read_dir(dir)?.count();
 
     Ok(VideoMetadata::create(fps, frame_count))
-}
\ No newline at end of file
+}
+/// Extracts the audio track of `input` to `path` as raw PCM (see [`AUDIO_SAMPLE_RATE`]). Returns
+/// `false` if the input has no audio track.
+pub async fn extract_audio(input: &str, path: &Path) -> Result<bool> {
+    let probe = Command::new("ffprobe")
+        .arg("-v").arg("0")
+        .arg("-of").arg("csv=p=0")
+        .arg("-select_streams").arg("a:0")
+        .arg("-show_entries").arg("stream=index")
+        .arg(input)
+        .output().await
+        .map_err(|e| Error::FFmpegError(
+            format!("failed to execute ffprobe: {e}")
+        ))?;
+    if probe.stdout.iter().all(u8::is_ascii_whitespace) {
+        return Ok(false);
+    }
+
+    let output = Command::new("ffmpeg")
+        .arg("-y")
+        .arg("-loglevel").arg("error")
+        .arg("-i").arg(input)
+        .arg("-vn")
+        .arg("-f").arg("s16le")
+        .arg("-ar").arg(AUDIO_SAMPLE_RATE.to_string())
+        .arg("-ac").arg(AUDIO_CHANNELS.to_string())
+        .arg(path)
+        .output().await
+        .map_err(|e| Error::FFmpegError(
+            format!("failed to execute ffmpeg: {e}")
+        ))?;
+    if !output.status.success() {
+        return Err(Error::FFmpegError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string()
+        ));
+    }
+    Ok(true)
+}
+
+/// ffmpeg process encoding raw frames into a video file
+pub struct VideoEncoder {
+    child: std::process::Child,
+    stdin: Option<std::process::ChildStdin>,
+    width: usize,
+    height: usize,
+}
+
+impl VideoEncoder {
+    /// Starts encoding `width`x`height` frames at `fps` into `output`, overwriting it
+    pub fn spawn(output: &str, width: usize, height: usize, fps: f64) -> Result<Self> {
+        let mut child = std::process::Command::new("ffmpeg")
+            .arg("-y")
+            .arg("-loglevel").arg("error")
+            .arg("-f").arg("rawvideo")
+            .arg("-pix_fmt").arg("rgb24")
+            .arg("-s").arg(format!("{width}x{height}"))
+            .arg("-r").arg(fps.to_string())
+            .arg("-i").arg("-")
+            // most encoders need an even size for yuv420p
+            .arg("-vf").arg("pad=ceil(iw/2)*2:ceil(ih/2)*2")
+            .arg("-pix_fmt").arg("yuv420p")
+            .arg(output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::FFmpegError(
+                format!("failed to execute ffmpeg: {e}")
+            ))?;
+
+        let stdin = child.stdin.take();
+        Ok(Self { child, stdin, width, height })
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> Result<()> {
+        if (frame.width(), frame.height()) != (self.width, self.height) {
+            return Err(Error::Custom(format!(
+                "frame size changed from {}x{} to {}x{} during export",
+                self.width, self.height, frame.width(), frame.height()
+            )));
+        }
+        let bytes = frame.data().iter().flat_map(|c| [c.r, c.g, c.b]).collect::<Vec<_>>();
+        let stdin = self.stdin.as_mut().expect("write() called after finish()");
+        // a closed pipe means ffmpeg gave up, finish() reports why
+        if stdin.write_all(&bytes).is_err() {
+            self.finish()?;
+            return Err(Error::FFmpegError("ffmpeg stopped reading frames".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Waits for ffmpeg to write the rest of the video
+    pub fn finish(&mut self) -> Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()
+            .map_err(|e| Error::FFmpegError(
+                format!("failed to execute ffmpeg: {e}")
+            ))?;
+        if status.success() {
+            return Ok(());
+        }
+
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            std::io::Read::read_to_string(&mut pipe, &mut stderr)?;
+        }
+        Err(Error::FFmpegError(stderr.trim().to_string()))
+    }
+}
@@ -0,0 +1,188 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Algorithm ffmpeg scales frames with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scaler {
+    #[default]
+    Bicubic,
+    Bilinear,
+    Area,
+    /// Nearest neighbor, keeps the edges of pixel art sharp
+    Neighbor,
+    Lanczos,
+}
+
+impl Scaler {
+    fn flags(&self) -> &'static str {
+        match self {
+            Scaler::Bicubic => "bicubic",
+            Scaler::Bilinear => "bilinear",
+            Scaler::Area => "area",
+            Scaler::Neighbor => "neighbor",
+            Scaler::Lanczos => "lanczos",
+        }
+    }
+}
+
+/// How a video is fit into `--width`x`--height` when both are given
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fit {
+    /// Scale to exactly that size, ignoring the aspect ratio
+    #[default]
+    Stretch,
+    /// Scale to fit inside, keeping the aspect ratio, and fill the rest with black
+    Contain,
+    /// Scale to cover all of it, keeping the aspect ratio, and cut off the rest
+    Cover,
+}
+
+/// Clockwise rotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+pub enum Rotation {
+    #[serde(rename = "90")]
+    #[value(name = "90")]
+    Rotate90,
+    #[serde(rename = "180")]
+    #[value(name = "180")]
+    Rotate180,
+    #[serde(rename = "270")]
+    #[value(name = "270")]
+    Rotate270,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+/// Filters applied by ffmpeg when extracting frames, in this order: crop, rotation, flip, color
+/// adjustments, custom filters and finally scaling.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VideoFilters {
+    /// Region of the source to keep, as `width:height:x:y` (ffmpeg crop syntax)
+    pub crop: Option<String>,
+    pub rotate: Option<Rotation>,
+    pub flip: Option<Flip>,
+    /// -1.0 to 1.0, 0.0 keeps the brightness
+    pub brightness: Option<f32>,
+    /// 1.0 keeps the contrast
+    pub contrast: Option<f32>,
+    /// 0.0 is grayscale, 1.0 keeps the saturation
+    pub saturation: Option<f32>,
+    /// 1.0 keeps the gamma
+    pub gamma: Option<f32>,
+    /// Additional ffmpeg filters (comma-separated), applied before scaling
+    pub custom: Option<String>,
+    pub scaler: Scaler,
+    pub fit: Fit,
+}
+
+impl VideoFilters {
+    /// Filter chain producing frames at `fps` with a size of `width`x`height` (-1 keeps the
+    /// aspect ratio, -1 for both keeps the source size)
+    pub fn chain(&self, fps: f64, width: i32, height: i32) -> String {
+        let mut filters = vec![format!("fps={fps}")];
+
+        if let Some(crop) = &self.crop {
+            filters.push(format!("crop={crop}"));
+        }
+        match self.rotate {
+            Some(Rotation::Rotate90) => filters.push("transpose=clock".to_string()),
+            Some(Rotation::Rotate180) => filters.push("hflip,vflip".to_string()),
+            Some(Rotation::Rotate270) => filters.push("transpose=cclock".to_string()),
+            None => {}
+        }
+        match self.flip {
+            Some(Flip::Horizontal) => filters.push("hflip".to_string()),
+            Some(Flip::Vertical) => filters.push("vflip".to_string()),
+            Some(Flip::Both) => filters.push("hflip,vflip".to_string()),
+            None => {}
+        }
+        let eq = [
+            ("brightness", self.brightness),
+            ("contrast", self.contrast),
+            ("saturation", self.saturation),
+            ("gamma", self.gamma),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.map(|v| format!("{name}={v}")))
+        .collect::<Vec<_>>();
+        if !eq.is_empty() {
+            filters.push(format!("eq={}", eq.join(":")));
+        }
+        if let Some(custom) = self.custom.as_ref().filter(|c| !c.trim().is_empty()) {
+            filters.push(custom.trim().to_string());
+        }
+
+        let flags = self.scaler.flags();
+        let fit = match (width > 0 && height > 0).then_some(self.fit) {
+            Some(Fit::Contain) => Some(("decrease", format!("pad={width}:{height}:(ow-iw)/2:(oh-ih)/2"))),
+            Some(Fit::Cover) => Some(("increase", format!("crop={width}:{height}"))),
+            Some(Fit::Stretch) | None => None,
+        };
+        match fit {
+            Some((aspect, fill)) => filters.push(format!(
+                "scale={width}:{height}:force_original_aspect_ratio={aspect}:flags={flags},{fill}"
+            )),
+            None => filters.push(format!("scale={width}:{height}:flags={flags}")),
+        }
+
+        filters.join(",")
+    }
+
+    /// Describes the filters for the cache key. The frame-rate and the size are part of the key
+    /// already; the fit and the scaler are added explicitly, as the chain only uses them for some
+    /// sizes.
+    pub fn cache_key(&self) -> String {
+        format!("{}|{:?}|{:?}", self.chain(0.0, 1, 1), self.fit, self.scaler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_chain() {
+        assert_eq!(VideoFilters::default().chain(30.0, -1, 240), "fps=30,scale=-1:240:flags=bicubic");
+    }
+
+    #[test]
+    fn test_full_chain() {
+        let filters = VideoFilters {
+            crop: Some("640:480:0:0".to_string()),
+            rotate: Some(Rotation::Rotate90),
+            flip: Some(Flip::Horizontal),
+            brightness: Some(0.1),
+            contrast: None,
+            saturation: Some(0.0),
+            gamma: None,
+            custom: Some("negate".to_string()),
+            scaler: Scaler::Neighbor,
+            fit: Fit::Contain,
+        };
+        assert_eq!(
+            filters.chain(12.5, 64, 32),
+            "fps=12.5,crop=640:480:0:0,transpose=clock,hflip,eq=brightness=0.1:saturation=0,negate,\
+             scale=64:32:force_original_aspect_ratio=decrease:flags=neighbor,pad=64:32:(ow-iw)/2:(oh-ih)/2"
+        );
+        // fitting needs both sides
+        assert!(filters.chain(12.5, 64, -1).ends_with("scale=64:-1:flags=neighbor"));
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let stretch = VideoFilters::default();
+        let contain = VideoFilters { fit: Fit::Contain, ..VideoFilters::default() };
+        let lanczos = VideoFilters { scaler: Scaler::Lanczos, ..VideoFilters::default() };
+        assert_ne!(stretch.cache_key(), contain.cache_key());
+        assert_ne!(stretch.cache_key(), lanczos.cache_key());
+        assert_eq!(stretch.cache_key(), VideoFilters::default().cache_key());
+    }
+}
@@ -4,11 +4,11 @@ use std::path::PathBuf;
 
 use rayon::prelude::*;
 
-use crate::paths;
 use crate::{
-    Result, Error,
+    Result, Error, FrameCache,
     color::Color, 
-    pixel::Pixel
+    pixel::Pixel,
+    tiling::Region,
 };
 
 
@@ -19,9 +19,13 @@ pub struct FrameFile {
 }
 
 impl FrameFile {
+    /// Frame of the main input
     pub fn new(idx: usize) -> Self {
-        let path = paths::frame_file(idx);
-        
+        Self::in_cache(&FrameCache::primary(), idx)
+    }
+    pub fn in_cache(cache: &FrameCache, idx: usize) -> Self {
+        let path = cache.frame_file(idx);
+
         Self { idx, path }
     }
     pub fn idx(&self) -> usize { self.idx }
@@ -69,6 +73,11 @@ pub struct Frame {
 }
 
 impl Frame {
+    pub fn new(width: usize, height: usize, data: impl Into<Box<[Color]>>) -> Self {
+        let data = data.into();
+        assert_eq!(data.len(), width * height, "frame data does not match dimensions");
+        Self { width, height, data }
+    }
     pub fn debug(width: usize, height: usize) -> Self {
         let data = vec![Color::new(128, 128, 128); width * height].into(); 
         Self { width, height, data }    
@@ -89,8 +98,7 @@ impl Frame {
     pub fn apply_pixels(&self, pixels: &Vec<Pixel>) -> Self {
         let mut data = self.data.clone();
         for p in pixels {
-            let i = p.y * self.width + p.x;
-            data[i] = p.color;
+            data[p.index(self.width)] = p.color;
         }
         Self {
             width: self.width,
@@ -98,6 +106,18 @@ impl Frame {
             data,
         }
     }
+    /// Like [`Frame::apply_frame_data`], without copying the frame
+    pub fn apply_frame_data_mut(&mut self, data: &FrameData) {
+        match data {
+            FrameData::Delta(d) => {
+                for p in d {
+                    self.data[p.index(self.width)] = p.color;
+                }
+            }
+            FrameData::Full { .. } => *self = Frame::from(data.clone()),
+            FrameData::Empty => {}
+        }
+    }
     pub fn apply_frame_data(&self, data: &FrameData) -> Self {
         match data {
             FrameData::Delta(d) => self.apply_pixels(d),
@@ -110,14 +130,44 @@ impl Frame {
         }
     }
 
+    /// Copy of a region of this frame
+    pub fn crop(&self, region: &Region) -> Frame {
+        let data = (region.y..region.y + region.height)
+            .flat_map(|y| {
+                let start = y * self.width + region.x;
+                &self.data[start..start + region.width]
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        Frame::new(region.width, region.height, data)
+    }
+    /// Copies `other` into this frame, with its top left corner at (x, y)
+    pub fn paste(&mut self, other: &Frame, x: usize, y: usize) {
+        for (row, src) in other.data.chunks(other.width.max(1)).enumerate() {
+            let start = (y + row) * self.width + x;
+            self.data[start..start + src.len()].copy_from_slice(src);
+        }
+    }
+
+    /// Exact delta that turns `self` into `other`
+    pub fn diff(&self, other: &Frame) -> FrameData {
+        if self.width != other.width || self.height != other.height {
+            return other.to_full_frame_data();
+        }
+        let pixels = self.data.par_iter()
+            .zip(other.data.par_iter())
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, (_, b))| Pixel::at_index(i, self.width, *b))
+            .collect::<Vec<_>>();
+
+        if pixels.is_empty() { FrameData::Empty } else { FrameData::Delta(pixels) }
+    }
+
     pub fn to_pixels(&self) -> Vec<Pixel> {
         self.data.into_par_iter()
             .enumerate()
-            .map(|(i, v)| {
-                let x = i % self.width;
-                let y = i / self.width;
-                Pixel { x, y, color: *v }
-            })
+            .map(|(i, v)| Pixel::at_index(i, self.width, *v))
             .collect()
     }
 }
@@ -129,22 +179,41 @@ pub enum FrameData {
     Empty
 }
 impl FrameData {
+    /// Number of pixels that will be sent for this frame
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Delta(d) => d.len(),
+            Self::Full { data, .. } => data.len(),
+            Self::Empty => 0,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
     pub fn to_pixels(self) -> Vec<Pixel> {
         match self {
             Self::Delta(d) => d,
             Self::Full { width: w, height: h, data: d } => {
                 (0..w as usize * h as usize)
                     .into_par_iter()
-                    .map(|i| {
-                        let x = i % w as usize;
-                        let y = i / w as usize;
-                        Pixel { x, y, color: d[i] }
-                    })
+                    .map(|i| Pixel::at_index(i, w as usize, d[i]))
                     .collect()
             },
             Self::Empty => Vec::new()
         }
     }
+    /// Iterates over the pixels of this frame without converting it
+    pub fn pixels(&self) -> Box<dyn Iterator<Item = Pixel> + '_> {
+        match self {
+            Self::Delta(d) => Box::new(d.iter().copied()),
+            Self::Full { width, data, .. } => Box::new(
+                data.iter()
+                    .enumerate()
+                    .map(|(i, c)| Pixel::at_index(i, *width as usize, *c))
+            ),
+            Self::Empty => Box::new(std::iter::empty()),
+        }
+    }
 }
 
 impl From<FrameData> for Frame {
@@ -156,12 +225,11 @@ impl From<FrameData> for Frame {
                 data: data.into(),
             },
             FrameData::Delta(d) => {
-                let width = d.iter().map(|p| p.x).max().unwrap_or(0) + 1;
-                let height = d.iter().map(|p| p.y).max().unwrap_or(0) + 1;
-                let mut data: Vec<Color> = vec![Color::new(128, 128, 128); width * height].into();
+                let width = d.iter().map(Pixel::x).max().unwrap_or(0) + 1;
+                let height = d.iter().map(Pixel::y).max().unwrap_or(0) + 1;
+                let mut data: Vec<Color> = vec![Color::new(128, 128, 128); width * height];
                 for p in d {
-                    let i = p.y * width + p.x;
-                    data[i] = p.color;
+                    data[p.index(width)] = p.color;
                 }
                 Self { width, height, data: data.into_boxed_slice() }
             },
@@ -1,14 +1,33 @@
-#![feature(io_error_more)]
-
 mod ffmpeg_cli;
 mod cache;
 mod args;
 mod compression;
 mod frame;
 mod color;
+mod color_planes;
 mod pixel;
 mod config;
 mod protocol;
+mod metrics;
+mod analysis;
+mod quantize;
+mod bitframe;
+mod order;
+mod pipeline;
+mod chunk;
+mod spill;
+mod tiling;
+mod output;
+mod compositor;
+mod playlist;
+mod control;
+mod tui;
+mod preview;
+mod record;
+mod audio;
+mod text;
+mod subtitles;
+mod filters;
 
 use colored::Colorize;
 pub use ffmpeg_cli::*;
@@ -17,9 +36,30 @@ pub use args::*;
 pub use compression::*;
 pub use frame::*;
 pub use color::*;
+pub use color_planes::*;
 pub use pixel::*;
 pub use config::*;
 pub use protocol::*;
+pub use metrics::*;
+pub use analysis::*;
+pub use quantize::*;
+pub use bitframe::*;
+pub use order::*;
+pub use pipeline::*;
+pub use chunk::*;
+pub use spill::*;
+pub use tiling::*;
+pub use output::*;
+pub use compositor::*;
+pub use playlist::*;
+pub use control::*;
+pub use tui::*;
+pub use preview::*;
+pub use record::*;
+pub use audio::*;
+pub use text::*;
+pub use subtitles::*;
+pub use filters::*;
 
 pub mod paths;
 
@@ -1,24 +1,42 @@
-#![feature(sync_unsafe_cell)]
-#![feature(ptr_as_ref_unchecked)]
+use clap::Parser;
 use clap_serde_derive::ClapSerde;
 use rayon::{prelude::*, ThreadPool};
-use std::cell::SyncUnsafeCell;
-use std::io::Write;
-use std::net::TcpStream;
-use std::sync::mpsc::channel;
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 use bad_apple_flut::*;
 use colored::Colorize;
 
 struct Context {
+    config: Arc<Config>,
     args: Args,    
-    stream: Option<Arc<Mutex<TcpStream>>>,
+    outputs: Vec<Output>,
+    /// Connections to `outputs`, empty until playback starts
+    senders: Vec<OutputSender>,
     metadata: VideoMetadata,
-    thread_pool: ThreadPool,
+    /// Loads frames with the layers drawn on top
+    compositor: Arc<Compositor>,
+    thread_pool: Arc<ThreadPool>,
+    quantizer: Option<Quantizer>,
+    control: Option<Arc<ControlServer>>,
+    /// Set once playback starts, when the TUI is enabled
+    tui: Option<Arc<Mutex<Tui>>>,
+    /// Set once playback starts, with `--dry-run` (unless the TUI shows the preview)
+    preview: Option<Arc<Mutex<Preview>>>,
+    /// Set before connecting, with `--record`
+    recorder: Option<Arc<Recorder>>,
+    /// Audio track of the video, with `--audio`
+    audio: Option<Arc<AudioTrack>>,
+    /// Set once playback starts, with `--audio`
+    audio_output: Option<Arc<Mutex<AudioOutput>>>,
 }
 
+/// How long a paused loop waits for a key press before checking the control socket again
+const PAUSE_POLL: Duration = Duration::from_millis(50);
+
 struct FrameTimer {
     start: std::time::Instant,
     delay: u64,
@@ -60,194 +78,574 @@ impl FrameTimer {
     }
 }
 
-fn compress_frames_to_vec(
-    context: &Context,
-    compressor: VideoCompressor,
-) -> Result<Vec<FrameData>> {
-    println!("{} {}", "::".blue(), "Compressing frames ...");
+fn print_status(line: &str) {
+    crossterm::execute!(
+        std::io::stdout(),
+        crossterm::cursor::MoveToColumn(0),
+        crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine),
+        crossterm::style::Print(line)
+    ).unwrap();
+}
 
-    let frame_data_vec = Arc::new(SyncUnsafeCell::new(vec![
-        FrameData::Empty;
-        context.metadata.frame_count
-    ]));    
+/// Prints a message, or shows it in the TUI
+fn notify(context: &Context, message: String) {
+    match &context.tui {
+        Some(tui) => tui.lock().unwrap().set_message(message),
+        None => println!("{} {}", "::".blue(), message),
+    }
+}
 
-    let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+/// Gives the terminal back before the process exits
+fn restore_terminal(context: &Context) {
+    if let Some(tui) = &context.tui {
+        tui.lock().unwrap().restore();
+    }
+}
 
-    let progress = progress_tracker(
-        counter.clone(),
-        context.metadata.frame_count,
-        "frames compressed".to_string(),
-    );
+/// Shows the state of playback and the frame that was just sent (if any) in the TUI
+fn update_tui(context: &Context, state: &PlayState, frame_data: Option<&FrameData>, force: bool) {
+    let Some(tui) = &context.tui else { return };
+    let mut tui = tui.lock().unwrap();
+    if let Some(frame_data) = frame_data {
+        tui.record(frame_data, context.senders.iter().map(OutputSender::bytes_sent).sum());
+    }
+    if let Err(e) = tui.draw(&playback_status(context, state), state.lag, force) {
+        tui.restore();
+        eprintln!("{} {}", "::".red(), e);
+        std::process::exit(1);
+    }
+}
 
-    let frame_files = (1..=context.metadata.frame_count)
-        .into_par_iter()
-        .map(|i| FrameFile::new(i))
+/// Plays the audio of frame `idx`, which was just sent
+fn sync_audio(context: &Context, idx: usize) {
+    let (Some(output), Some(track)) = (&context.audio_output, &context.audio) else { return };
+    let fps = context.metadata.fps;
+    if let Err(e) = output.lock().unwrap().sync(track, (idx - 1) as f64 / fps, idx as f64 / fps) {
+        notify(context, e.to_string());
+    }
+}
+
+/// Number of frames slow outputs had to skip, if any
+fn skipped_frames(context: &Context) -> String {
+    let skipped = context
+        .senders
+        .iter()
+        .filter(|s| s.coalesced() > 0)
+        .map(|s| format!("{} {}", s.output().host, s.coalesced()))
         .collect::<Vec<_>>();
-    
-    let chunks = frame_files.chunks(
-        match context.args.aot_frame_group_size {
-            0 => frame_files.len(),
-            n => n,
-        }
-    );
+
+    match skipped.is_empty() {
+        true => String::new(),
+        false => format!(" | skipped frames: {}", skipped.join(", ")),
+    }
+}
+
+fn report_metrics(context: &Context, metrics: &MetricsRecorder) -> Result<()> {
+    if let Some(summary) = metrics.summary().filter(|_| context.tui.is_none()) {
+        println!("{} Quality metrics:", "::".blue());
+        println!("{}", summary);
+    }
+    if let Some(path) = &context.args.metrics_csv {
+        metrics.write_csv(path)?;
+        notify(context, format!("Per-frame metrics written to {}", path));
+    }
+    Ok(())
+}
+
+/// Compressed frames of the whole video, kept in RAM or spilled to disk
+enum AotFrames {
+    Memory(Vec<FrameData>),
+    Spilled(SpillFile),
+}
+
+/// Compresses all frames. Without `progress`, nothing is printed.
+fn compress_frames_ahead_of_time(
+    context: &Context,
+    compressor: TiledCompressor,
+    progress: bool,
+) -> Result<(AotFrames, MetricsRecorder)> {
+    let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+    let tracker = progress.then(|| {
+        println!("{} Compressing frames ...", "::".blue());
+        progress_tracker(
+            counter.clone(),
+            context.metadata.frame_count,
+            "frames compressed".to_string(),
+        )
+    });
+
+    let frame_indices = (1..=context.metadata.frame_count).collect::<Vec<_>>();
+
+    let group_size = match context.args.aot_frame_group_size {
+        0 => frame_indices.len().max(1),
+        n => n,
+    };
+
+    // when spilling, only one chunk per thread is kept in RAM at a time
+    let window_size = match context.args.spill {
+        true => group_size.saturating_mul(context.args.compress_threads),
+        false => frame_indices.len().max(1),
+    };
 
     let thread_pool = rayon::ThreadPoolBuilder::new()
         .num_threads(context.args.compress_threads)
         .build()
         .expect("Failed to create thread pool");
 
-    let compressors = Arc::new(SyncUnsafeCell::new(vec![compressor.clone(); chunks.len()]));
+    let mut frame_data_vec = Vec::new();
+    let mut spill = context.args.spill
+        .then(|| SpillWriter::create(context.args.spill_compression))
+        .transpose()?;
+    let mut metrics = MetricsRecorder::new();
+    let mut prev_canvas = None;
+    // stitched frames get the budget of the compressors, which is per tile on a wall
+    let budget = CompressionLevelArg::try_from(context.args.compression_level.clone())
+        .map_err(|e| Error::InvalidArgs(e.to_string()))?
+        .per_frame(context.metadata.fps)
+        .pixel_budget()
+        .map(|n| n * compressor.tile_count());
 
-    thread_pool.scope(|s| {
-        let (err_tx, err_rx) = channel::<String>();
-        let err_tx = Arc::new(Mutex::new(err_tx));
-        let err_rx = Arc::new(Mutex::new(err_rx));
+    for window in frame_indices.chunks(window_size) {
+        // every chunk gets a fresh compressor, so its first frame is a keyframe
+        let mut chunks = thread_pool.install(|| {
+            window
+                .par_chunks(group_size)
+                .map(|chunk| compress_chunk(context, compressor.clone(), chunk, &counter))
+                .collect::<Result<Vec<_>>>()
+        })?;
 
-        for (i, chunk) in chunks.enumerate() {
-            let counter = Arc::clone(&counter);
-            let compressors = Arc::clone(&compressors);
-            let frame_data_vec = Arc::clone(&frame_data_vec);
-            let err_tx = Arc::clone(&err_tx);
-            let err_rx = Arc::clone(&err_rx);
+        // debug frames don't reflect the canvas, so there is nothing to stitch against
+        if !context.args.debug {
+            prev_canvas = stitch_chunks(prev_canvas.take(), &mut chunks, budget);
+        }
 
-            if let Ok(e) = err_rx.lock().unwrap().try_recv() {
-                return Err(Error::Custom(e));
+        for chunk in chunks {
+            metrics.extend(chunk.metrics);
+            match &mut spill {
+                Some(spill) => chunk.frames.iter().try_for_each(|f| spill.write(f))?,
+                None => frame_data_vec.extend(chunk.frames),
             }
+        }
+    }
 
-            s.spawn(move |_| {
-                let mut thread_frame_data_vec = Box::new(Vec::new());
-                let compressor = unsafe { &mut compressors.get().as_mut_unchecked()[i] };
+    if let Some(tracker) = tracker {
+        tracker.join().unwrap();
+    }
 
-                for frame_file in chunk {
-                    if let Ok(_) = err_rx.lock().unwrap().try_recv() {
-                        return;
-                    }
+    let frames = match spill {
+        Some(spill) => {
+            let spill = spill.finish()?;
+            if progress {
+                println!(
+                    "{} Spilled {} frames to disk ({:.1} MiB)",
+                    "::".blue(),
+                    spill.frame_count(),
+                    spill.size()? as f64 / (1024.0 * 1024.0)
+                );
+            }
+            AotFrames::Spilled(spill)
+        }
+        None => AotFrames::Memory(frame_data_vec),
+    };
 
-                    let frame = frame_file.load();
+    Ok((frames, metrics))
+}
 
-                    if let Err(e) = frame {
-                        err_tx
-                            .lock()
-                            .unwrap()
-                            .send(format!("Failed to load frame {}: {}", frame_file.idx(), e))
-                            .unwrap_or_else(|_| {});
-                        return;
-                    }
+/// Compresses a group of consecutive frames, in order
+fn compress_chunk(
+    context: &Context,
+    mut compressor: TiledCompressor,
+    chunk: &[usize],
+    counter: &std::sync::atomic::AtomicUsize,
+) -> Result<CompressedChunk> {
+    let mut frame_data_vec = Vec::with_capacity(chunk.len());
+    let mut metrics = Vec::new();
+    let mut quantizer = context.quantizer.clone();
 
-                    let frame_data = compressor.compress_frame(&frame.unwrap());
-                    thread_frame_data_vec.push(frame_data);
-                    counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                }
+    for &idx in chunk {
+        let frame = context.compositor.load_frame(idx).map_err(|e| {
+            Error::Custom(format!("Failed to load frame {}: {}", idx, e))
+        })?;
 
-                let frame_data_vec = unsafe { frame_data_vec.get().as_mut_unchecked() };
-                frame_data_vec.splice(
-                    chunk[0].idx() - 1..chunk[0].idx() - 1 + thread_frame_data_vec.len(),
-                    thread_frame_data_vec.into_iter(),
-                );
-            });
+        let quantized = quantizer.as_mut().map(|q| q.process(&frame));
+        let frame_data = compressor.compress_frame(quantized.as_ref().unwrap_or(&frame));
+        if context.args.metrics_enabled() {
+            metrics.push(compressor.metrics(idx, frame_data.len(), &frame));
         }
+        frame_data_vec.push(frame_data);
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
 
-        Ok(())
-    })?;
+    Ok(CompressedChunk {
+        frames: frame_data_vec,
+        metrics,
+        canvas: compressor.canvas().map(Cow::into_owned),
+    })
+}
 
-    progress.join().unwrap();
+fn send_frame(context: &Context, orderer: &mut PixelOrderer, frame_data: &FrameData) -> Result<()> {
+    if let Some(preview) = &context.preview {
+        preview.lock().unwrap().show(frame_data)?;
+    }
+    let pixels = orderer.pixels(frame_data);
+    if pixels.is_empty() {
+        return Ok(());
+    }
 
-    Ok(Arc::try_unwrap(frame_data_vec)
-        .expect("More than 1 strong reference (impossible)")
-        .into_inner())
-}
-
-fn send_frame(context: &Context, frame_data: &FrameData) -> Result<()> {
-    let pixels = frame_data.to_owned().to_pixels();
-    let len = pixels.len();
-    if len != 0 {
-        // send pixels
-        let msgs = pixels
-            .par_chunks(400)
-            .map(|chunk| {
-                pixels_to_cmds(
-                    context.args.protocol,
-                    context.args.canvas,
-                    chunk,
-                    context.args.x_offset,
-                    context.args.y_offset,
-                )
-            })
-            .collect::<Vec<_>>();
+    // all outputs get the frame in the same frame slot, only the tiles of a wall wait for each other
+    for sender in &context.senders {
+        sender.send(&pixels)?;
+    }
+    Ok(())
+}
 
-        context.thread_pool.scope(|s| {
-            let (err_tx, err_rx) = channel::<String>();
-            let err_tx = Arc::new(Mutex::new(err_tx));
+/// Whether the live metrics line should be refreshed on this frame (about once per second). The
+/// TUI replaces it.
+fn is_status_frame(context: &Context, idx: usize) -> bool {
+    context.tui.is_none() && idx.is_multiple_of((context.metadata.fps.round() as usize).max(1))
+}
 
-            for msg in msgs {
-                if let Ok(e) = err_rx.try_recv() {
-                    return Err(Error::Custom(e));
-                }
+/// Why a playback loop stopped
+enum LoopExit {
+    /// Played to the end (without `repeat`)
+    Finished,
+    /// Play again with new args (e.g. another input or compression level), starting at `frame`
+    Reload { args: Box<Args>, frame: usize },
+}
 
-                let stream = Arc::clone(&context.stream.as_ref().expect("Stream not initialized"));
-                let err_tx = Arc::clone(&err_tx);
-                s.spawn(move |_| {
-                    let result = match &mut stream.lock() {
-                        Ok(stream) => {
-                            let success = stream.write_all(&msg);
-                            match success {
-                                Ok(_) => {
-                                    stream.flush().unwrap();
-                                    Ok(())
-                                }
-                                Err(e) => match e.kind() {
-                                    std::io::ErrorKind::BrokenPipe => {
-                                        Err("Unable to send frame: Connection closed by server"
-                                            .to_string())
-                                    }
-                                    _ => Err("Unable to send frame: Unknown error".to_string()),
-                                },
-                            }
-                        }
-                        Err(_) => Err("Failed to lock stream".to_string()),
-                    };
-
-                    if let Err(e) = result {
-                        err_tx.lock().unwrap().send(e).unwrap_or_else(|_| {
-                            // error probably already sent, rx out of scope
-                        });
-                    }
-                });
+/// What a playback loop has to do after a control command
+enum ControlAction {
+    Continue,
+    /// Continue playback at the given frame
+    Seek(usize),
+    Reload { args: Box<Args>, frame: usize },
+}
+
+/// Position of a playback loop, reported by the `status` command
+#[derive(Default)]
+struct PlayState {
+    paused: bool,
+    /// Last frame sent
+    frame: usize,
+    /// How far playback is behind (in ms)
+    lag: u64,
+}
+
+fn playback_status(context: &Context, state: &PlayState) -> PlaybackStatus {
+    PlaybackStatus {
+        input: context.args.input[0].clone(),
+        frame: state.frame,
+        frame_count: context.metadata.frame_count,
+        fps: context.metadata.fps,
+        paused: state.paused,
+        x_offset: context.args.x_offset,
+        y_offset: context.args.y_offset,
+        compression_level: context.args.compression_level.clone(),
+        jit: context.args.jit,
+    }
+}
+
+/// Next command from the control socket or a key press in the TUI. While paused, waits for one
+/// (with the TUI, for up to [`PAUSE_POLL`]).
+fn next_command(context: &Context, state: &PlayState) -> Option<(ControlCommand, Option<ControlRequest>)> {
+    if let Some(request) = context.control.as_ref().and_then(|c| c.try_recv()) {
+        return Some((request.command.clone(), Some(request)));
+    }
+    let Some(tui) = &context.tui else {
+        let request = context.control.as_ref().filter(|_| state.paused)?.recv()?;
+        return Some((request.command.clone(), Some(request)));
+    };
+
+    let timeout = if state.paused { PAUSE_POLL } else { Duration::ZERO };
+    let input = tui.lock().unwrap().poll_input(&playback_status(context, state), timeout);
+    match input {
+        Ok(Some(TuiInput::Command(command))) => Some((command, None)),
+        Ok(Some(TuiInput::Quit)) => {
+            restore_terminal(context);
+            std::process::exit(0);
+        }
+        Ok(None) => None,
+        Err(e) => {
+            restore_terminal(context);
+            eprintln!("{} {}", "::".red(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Handles queued control commands and key presses. While paused, waits for commands until
+/// playback resumes or the loop has to seek or reload.
+fn poll_control(context: &mut Context, state: &mut PlayState) -> ControlAction {
+    if context.control.is_none() && context.tui.is_none() {
+        return ControlAction::Continue;
+    }
+
+    loop {
+        let Some((command, request)) = next_command(context, state) else {
+            if state.paused && context.tui.is_some() {
+                update_tui(context, state, None, false);
+                continue;
             }
+            return ControlAction::Continue;
+        };
 
-            Ok(())
-        })
-    } else {
-        Ok(())
+        let result = handle_command(context, state, &command);
+        match (request, &result) {
+            (Some(request), Ok(_)) => request.reply(match command {
+                ControlCommand::Status => ControlResponse::status(playback_status(context, state)),
+                _ => ControlResponse::ok(),
+            }),
+            (Some(request), Err(e)) => request.reply(ControlResponse::error(e)),
+            (None, Err(e)) => notify(context, e.to_string()),
+            (None, Ok(_)) => {}
+        }
+        if let Ok(Some(action)) = result {
+            if let ControlAction::Reload { args, .. } = &action {
+                notify(context, format!("Preparing {} ...", args.input[0]));
+            }
+            update_tui(context, state, None, true);
+            return action;
+        }
+        update_tui(context, state, None, true);
     }
 }
 
-fn loop_just_in_time(context: &Context, mut compressor: VideoCompressor) -> Result<()> {
+fn handle_command(
+    context: &mut Context,
+    state: &mut PlayState,
+    command: &ControlCommand,
+) -> Result<Option<ControlAction>> {
+    let action = match command {
+        ControlCommand::Pause => {
+            state.paused = true;
+            None
+        }
+        ControlCommand::Resume => {
+            state.paused = false;
+            None
+        }
+        ControlCommand::Status => None,
+        ControlCommand::Seek { frame } => {
+            if !(1..=context.metadata.frame_count).contains(frame) {
+                return Err(Error::InvalidArgs(format!(
+                    "frame must be between 1 and {}", context.metadata.frame_count
+                )));
+            }
+            Some(ControlAction::Seek(*frame))
+        }
+        ControlCommand::Offset { x, y } => {
+            let mut args = context.args.clone();
+            (args.x_offset, args.y_offset) = (*x, *y);
+            let mut outputs = resolve_outputs(&context.config, &args)?;
+            fit_outputs(&mut outputs, &context.compositor)?;
+
+            // the whole frame has to be drawn again at the new position
+            context.args = args;
+            context.outputs = outputs;
+            context.senders.clear();
+            connect_outputs(context, None);
+            Some(ControlAction::Seek(state.frame.max(1)))
+        }
+        ControlCommand::CompressionLevel { level } => {
+            CompressionLevelArg::try_from(level.clone())
+                .map_err(|e| Error::InvalidArgs(e.to_string()))?;
+            let mut args = context.args.clone();
+            args.compression_level = level.clone();
+            Some(ControlAction::Reload { args: Box::new(args), frame: state.frame.max(1) })
+        }
+        ControlCommand::Input { input } => {
+            verify_input(input)?;
+            let mut args = context.args.clone();
+            args.input = vec![input.clone()];
+            Some(ControlAction::Reload { args: Box::new(args), frame: 1 })
+        }
+    };
+    Ok(action)
+}
+
+/// Time every output has spent sending so far
+fn send_times(context: &Context) -> Vec<std::time::Duration> {
+    context.senders.iter().map(OutputSender::busy).collect()
+}
+
+/// Plays the video from frame `start`, once or forever with `repeat`
+fn loop_just_in_time(
+    context: &mut Context,
+    compressor: &TiledCompressor,
+    repeat: bool,
+    start: usize,
+) -> Result<LoopExit> {
     let mut timer = FrameTimer::new(context.metadata.fps);
+    let mut metrics = context.args.metrics_enabled().then(MetricsRecorder::new);
+    let mut orderer = PixelOrderer::new(context.args.pixel_order, context.args.color_distance);
+    let collect_metrics = metrics.is_some();
+    let spawn = |context: &Context, start| JitPipeline::spawn(
+        context.metadata.frame_count,
+        start,
+        Arc::clone(&context.compositor),
+        context.args.jit_lookahead.unwrap_or(DEFAULT_JIT_LOOKAHEAD),
+        compressor.clone(),
+        context.quantizer.clone(),
+        collect_metrics,
+    );
+    let mut pipeline = spawn(context, start);
+    // the sender threads outlive the pipeline, only their time since it was spawned counts
+    let mut send_start = send_times(context);
+    let mut state = PlayState { frame: start - 1, ..Default::default() };
+
     loop {
-        for i in 1..=context.metadata.frame_count {
-            timer.start();
-            let frame = FrameFile::new(i).load()?;
-            let frame_data = compressor.compress_frame(&frame);
-            send_frame(&context, &frame_data).unwrap_or_else(|e| {
+        match poll_control(context, &mut state) {
+            ControlAction::Continue => {}
+            // a fresh compressor starts with a keyframe, which redraws the whole frame
+            ControlAction::Seek(frame) => {
+                pipeline = spawn(context, frame);
+                send_start = send_times(context);
+            }
+            ControlAction::Reload { args, frame } => return Ok(LoopExit::Reload { args, frame }),
+        }
+
+        timer.start();
+        let CompressedFrame { idx, pass, frame_data, metrics: frame_metrics } = pipeline.recv()?;
+        pipeline
+            .send_clock()
+            .busy(|| send_frame(context, &mut orderer, &frame_data))
+            .unwrap_or_else(|e| {
+                restore_terminal(context);
                 eprintln!("{}", e);
                 std::process::exit(1);
             });
-            timer.wait();
+        state.frame = idx;
+        sync_audio(context, idx);
+        update_tui(context, &state, Some(&frame_data), false);
+
+        if let (Some(metrics), Some(m)) = (&mut metrics, frame_metrics) {
+            if is_status_frame(context, idx) {
+                print_status(&format!("{}{}", m, skipped_frames(context)));
+            }
+            metrics.record(m);
+        }
+
+        // summarize the first full pass only
+        if pass == 0 && idx == context.metadata.frame_count {
+            if let Some(metrics) = metrics.take() {
+                if context.tui.is_none() {
+                    println!();
+                }
+                report_metrics(context, &metrics)?;
+                if context.tui.is_none() {
+                    println!("{} Pipeline stages (per frame):", "::".blue());
+                    let frame_time = std::time::Duration::from_secs_f64(1.0 / context.metadata.fps);
+                    let network = send_times(context)
+                        .iter()
+                        .zip(&send_start)
+                        .map(|(now, start)| now.saturating_sub(*start))
+                        .max()
+                        .unwrap_or_default();
+                    println!("{}", pipeline.stats(frame_time, network));
+                }
+            }
+        }
+        timer.wait();
+        state.lag = timer.lag;
+
+        if !repeat && idx == context.metadata.frame_count {
+            return Ok(LoopExit::Finished);
         }
     }
 }
 
-fn loop_ahead_of_time(context: &Context, frames: Vec<FrameData>) -> Result<()> {
+/// Reconstructs what the wall shows after the first `frame` frames. When spilled, also returns a
+/// reader positioned after them.
+fn aot_canvas(frames: &AotFrames, frame: usize) -> Result<(Option<Frame>, Option<SpillReader>)> {
+    let mut canvas: Option<Frame> = None;
+    let mut apply = |frame_data: &FrameData| match &mut canvas {
+        Some(canvas) => canvas.apply_frame_data_mut(frame_data),
+        None => canvas = Some(Frame::from(frame_data.clone())),
+    };
+    let reader = match frames {
+        AotFrames::Memory(frames) => {
+            frames[..frame].iter().for_each(&mut apply);
+            None
+        }
+        AotFrames::Spilled(spill) => {
+            let reader = spill.stream()?;
+            for _ in 0..frame {
+                apply(&reader.recv()?);
+            }
+            Some(reader)
+        }
+    };
+    Ok((canvas, reader))
+}
+
+/// Plays the video from frame `start`, once or forever with `repeat`
+fn loop_ahead_of_time(
+    context: &mut Context,
+    frames: &AotFrames,
+    metrics: &MetricsRecorder,
+    repeat: bool,
+    start: usize,
+) -> Result<LoopExit> {
     let mut timer = FrameTimer::new(context.metadata.fps);
+    let mut orderer = PixelOrderer::new(context.args.pixel_order, context.args.color_distance);
+    let frame_count = match frames {
+        AotFrames::Memory(frames) => frames.len(),
+        AotFrames::Spilled(spill) => spill.frame_count(),
+    };
+    let mut state = PlayState::default();
+    let mut seek = (start > 1).then_some(start);
+    let mut reader = match (frames, seek) {
+        (AotFrames::Spilled(spill), None) => Some(spill.stream()?),
+        _ => None,
+    };
+
     loop {
-        for frame_data in frames.iter() {
+        // 0-based index of the next frame
+        let mut i = 0;
+        while i < frame_count {
+            match poll_control(context, &mut state) {
+                ControlAction::Continue => {}
+                ControlAction::Seek(frame) => seek = Some(frame),
+                ControlAction::Reload { args, frame } => return Ok(LoopExit::Reload { args, frame }),
+            }
+
             timer.start();
-            send_frame(&context, &frame_data).unwrap_or_else(|e| {
+            let frame_data = match seek.take() {
+                // frames only hold the changes to the previous frame, so send the whole canvas
+                Some(frame) => {
+                    let (canvas, spill_reader) = aot_canvas(frames, frame)?;
+                    if spill_reader.is_some() {
+                        reader = spill_reader;
+                    }
+                    i = frame - 1;
+                    Cow::Owned(canvas.map(|c| c.to_full_frame_data()).unwrap_or(FrameData::Empty))
+                }
+                None => match (frames, &reader) {
+                    (_, Some(reader)) => Cow::Owned(reader.recv()?),
+                    (AotFrames::Memory(frames), None) => Cow::Borrowed(&frames[i]),
+                    (AotFrames::Spilled(_), None) => unreachable!(),
+                },
+            };
+            send_frame(context, &mut orderer, &frame_data).unwrap_or_else(|e| {
+                restore_terminal(context);
                 eprintln!("Error: {:?}", e);
                 std::process::exit(1);
             });
+            state.frame = i + 1;
+            sync_audio(context, i + 1);
+            update_tui(context, &state, Some(&frame_data), false);
+            if is_status_frame(context, i + 1) {
+                if let Some(m) = metrics.get(i + 1) {
+                    print_status(&format!("{}{}", m, skipped_frames(context)));
+                }
+            }
             timer.wait();
+            state.lag = timer.lag;
+            i += 1;
+        }
+        if !repeat {
+            return Ok(LoopExit::Finished);
         }
     }
 }
@@ -264,21 +662,11 @@ pub fn verify_args(args: &Args) -> Result<()> {
             "--fps must be greater than 0.0".to_string(),
         ));
     }
-    if args.input.is_empty() {
+    if args.input.is_empty() && args.playlist.is_none() {
         return Err(Error::InvalidArgs("No input file specified".to_string()));
     }
-    let path = std::path::Path::new(&args.input);
-    if !path.exists() {
-        return Err(Error::InvalidArgs(format!(
-            "Input file '{}' does not exist",
-            path.to_str().unwrap()
-        )));
-    }
-    if !path.is_file() {
-        return Err(Error::InvalidArgs(format!(
-            "Input file '{}' is not a file",
-            path.to_str().unwrap()
-        )));
+    for input in &args.input {
+        verify_input(input)?;
     }
     if args.send_threads == 0 {
         return Err(Error::InvalidConfig(
@@ -290,11 +678,6 @@ pub fn verify_args(args: &Args) -> Result<()> {
             "compress_threads must be greater than 0".to_string(),
         ));
     }
-    if args.host.is_none() && args.target.is_none() {
-        return Err(Error::InvalidConfig(
-            "host or target must be specified".to_string(),
-        ));
-    }    
     if args.aot_frame_group_size == 0 {
         return Err(Error::InvalidConfig(
             "aot_frame_group_size must be greater than 0".to_string(),
@@ -304,113 +687,659 @@ pub fn verify_args(args: &Args) -> Result<()> {
     Ok(())
 }
 
-fn connect(host: &str) -> Arc<Mutex<TcpStream>> {
-    let stream = TcpStream::connect(&host).unwrap_or_else(|e| {
-        eprintln!("{} Failed to connect to {}: {}", "::".red(), host, e);
-        std::process::exit(1);
-    });
+fn verify_input(input: &str) -> Result<()> {
+    let path = std::path::Path::new(input);
+    if !path.exists() {
+        return Err(Error::InvalidArgs(format!(
+            "Input file '{}' does not exist",
+            path.to_str().unwrap()
+        )));
+    }
+    if !path.is_file() {
+        return Err(Error::InvalidArgs(format!(
+            "Input file '{}' is not a file",
+            path.to_str().unwrap()
+        )));
+    }
+    Ok(())
+}
+
+/// Extracts the frames of the first input to the cache directory, unless a valid cache already
+/// exists
+async fn prepare_frames(args: &Args) -> Result<VideoMetadata> {
+    let input = args.input.first().ok_or_else(|| {
+        Error::InvalidArgs("No input file specified".to_string())
+    })?;
+    FrameCache::primary()
+        .prepare(
+            &args.cache_key(input),
+            input,
+            args.fps,
+            args.width.unwrap_or(-1),
+            args.height.unwrap_or(-1),
+            &args.video_filters(),
+            args.nocache,
+            true,
+        )
+        .await
+}
 
-    Arc::new(Mutex::new(stream))
+/// Looks up the layers selected with `--layers`
+fn find_layers(config: &Config, args: &Args) -> Result<Vec<Layer>> {
+    let Some(names) = &args.layers else { return Ok(Vec::new()) };
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            config.layers.get(name).cloned().ok_or_else(|| {
+                Error::InvalidConfig(format!("Layer '{}' not found in config", name))
+            })
+        })
+        .collect()
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {    
-    let config = Config::load().unwrap_or_else(
-        |e| {
-            eprintln!("Failed to load config:\n{}", e.to_string().red());
-            eprintln!(
-                "Edit the config file at [{}] to fix the problem.", 
-                paths::config_file().to_str().unwrap().cyan()
-            );
-            eprintln!(
-                "If you recently updated bad-apple-flut, you may need to add missing fields to the config file. See the latest README for details."
-            );            
-            std::process::exit(1);
-        }
+/// Looks up the text overlays selected with `--texts`
+fn find_texts(config: &Config, args: &Args) -> Result<Vec<TextOverlay>> {
+    let Some(names) = &args.texts else { return Ok(Vec::new()) };
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            config.texts.get(name).cloned().ok_or_else(|| {
+                Error::InvalidConfig(format!("Text '{}' not found in config", name))
+            })
+        })
+        .collect()
+}
+
+async fn analyze(args: &Args, levels: &[String]) -> Result<()> {
+    let metadata = prepare_frames(args).await?;
+
+    println!("{} Analyzing compression settings ...", "::".blue());
+
+    let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let progress = progress_tracker(
+        counter.clone(),
+        metadata.frame_count,
+        "frames analyzed".to_string(),
     );
 
-    let mut args = config.args.clone().merge_clap();
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.compress_threads)
+        .build()
+        .expect("Failed to create thread pool");
 
-    verify_args(&args)?;
+    let quantizer = args.quantizer()?;
+    let analysis = thread_pool.install(|| {
+        let options = CompressorOptions { debug: false, ..CompressorOptions::from(args) };
+        Analysis::run(&metadata, levels, &options, quantizer, args.x_offset, args.y_offset, counter)
+    })?;
 
-    match &args.target {
-        Some(target) => {
-            let target = config.targets.get(target).unwrap_or_else(|| {
-                eprintln!("Target '{}' not found in config", target);
-                std::process::exit(1);
-            });            
+    progress.join().unwrap();
+
+    println!("{}", analysis);
+    Ok(())
+}
+
+/// Sends a recording to the servers selected in `args`, or to the servers it was recorded from if
+/// none are selected
+fn replay_recording(config: &Config, args: &Args, recording: &str, fast: bool) -> Result<()> {
+    let path = std::path::Path::new(recording);
+    let streams = RecordingReader::streams(path)?;
+    if streams.is_empty() {
+        return Err(Error::InvalidArgs(format!("{} holds no streams", recording)));
+    }
 
-            args.host = Some(target.host.clone());
-            args.protocol = target.protocol.clone();
-            args.canvas = target.canvas;
+    let hosts = match (&args.host, &args.target, &args.wall) {
+        (None, None, None) => streams.iter().map(|s| s.host.clone()).collect::<Vec<_>>(),
+        _ => {
+            let outputs = resolve_outputs(config, args)?;
+            if outputs.len() != 1 && outputs.len() != streams.len() {
+                return Err(Error::InvalidArgs(format!(
+                    "{} holds {} streams, which cannot be sent to {} targets",
+                    recording, streams.len(), outputs.len()
+                )));
+            }
+            // the recorded bytes are sent as they are, so the protocol can't be changed
+            for (stream, output) in streams.iter().zip(outputs.iter().cycle()) {
+                if stream.protocol != output.protocol {
+                    println!(
+                        "{} {} was recorded with protocol {:?}, but {} uses {:?}",
+                        "::".yellow(), stream.host, stream.protocol, output.host, output.protocol
+                    );
+                }
+            }
+            outputs.into_iter().map(|o| o.host).collect()
         }
-        None => {}
+    };
+
+    println!("{} Replaying {} on {}", "::".blue(), recording, hosts.join(", "));
+    let stats = replay(path, &hosts, fast)?;
+    println!("{} Sent {}", "::".blue(), stats);
+    Ok(())
+}
+
+/// Looks up a target, or all targets of a target group
+fn find_targets<'a>(config: &'a Config, name: &str) -> Result<Vec<&'a Target>> {
+    let find = |name: &str| {
+        config.targets.get(name).ok_or_else(|| {
+            Error::InvalidConfig(format!("Target '{}' not found in config", name))
+        })
+    };
+    match config.groups.get(name) {
+        Some(group) => group.iter().map(|t| find(t)).collect(),
+        None => Ok(vec![find(name)?]),
+    }
+}
+
+/// Resolves the servers to play on from `--wall`, `--target` or `--host`. Dry runs and exports play
+/// on none.
+fn resolve_outputs(config: &Config, args: &Args) -> Result<Vec<Output>> {
+    if args.dry_run || args.export.is_some() {
+        return Ok(Vec::new());
+    }
+    let output = |host: &str, protocol, canvas, x_offset, y_offset, region, transform| Output {
+        host: host.to_string(),
+        protocol,
+        canvas,
+        x_offset: args.x_offset + x_offset,
+        y_offset: args.y_offset + y_offset,
+        region,
+        transform,
+    };
+    let target_output = |t: &Target, region| {
+        output(&t.host, t.protocol, t.canvas, t.x_offset, t.y_offset, region, t.transform)
+    };
+
+    if let Some(wall) = &args.wall {
+        if args.target.is_some() || args.host.is_some() {
+            return Err(Error::InvalidArgs(
+                "--wall cannot be combined with --target or --host".to_string(),
+            ));
+        }
+        let wall = config.walls.get(wall).ok_or_else(|| {
+            Error::InvalidConfig(format!("Wall '{}' not found in config", wall))
+        })?;
+
+        let mut outputs = Vec::new();
+        for tile in &wall.tiles {
+            for t in find_targets(config, &tile.target)? {
+                outputs.push(target_output(t, Some(tile.region)));
+            }
+        }
+        return Ok(outputs);
+    }
+
+    if let Some(targets) = &args.target {
+        let mut outputs = Vec::new();
+        for name in targets.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            for t in find_targets(config, name)? {
+                outputs.push(target_output(t, None));
+            }
+        }
+        return Ok(outputs);
+    }
+
+    match &args.host {
+        Some(host) => Ok(vec![output(host, args.protocol, args.canvas, 0, 0, None, OutputTransform::default())]),
+        None => Err(Error::InvalidConfig(
+            "host, target or wall must be specified".to_string(),
+        )),
+    }
+}
+
+/// Hands the terminal, the recording and the audio output of `previous` on to `context`, which replaces it
+fn inherit(context: &mut Context, previous: &Context) {
+    context.tui = previous.tui.clone();
+    context.preview = previous.preview.clone();
+    context.recorder = previous.recorder.clone();
+    context.audio_output = previous.audio_output.clone();
+}
+
+/// Tells transformed outputs the size of the frames, see [`Output::fit_frame`]
+fn fit_outputs(outputs: &mut [Output], compositor: &Compositor) -> Result<()> {
+    if outputs.iter().all(|o| o.transform.is_identity()) {
+        return Ok(());
     }
+    let first = compositor.load_frame(1)?;
+    for output in outputs {
+        output.fit_frame(first.width(), first.height());
+    }
+    Ok(())
+}
+
+/// Connects to the outputs of `context`, reusing the connections of `previous` if they go to the
+/// same outputs
+fn connect_outputs(context: &mut Context, previous: Option<&mut Context>) {
+    if let Some(previous) = previous.filter(|p| p.outputs == context.outputs) {
+        context.senders = std::mem::take(&mut previous.senders);
+        return;
+    }
+    if context.outputs.is_empty() {
+        return;
+    }
+    let pool = Arc::clone(&context.thread_pool);
+    let senders = context
+        .outputs
+        .iter()
+        .map(|output| {
+            let recording = context.recorder.as_ref().map(|r| r.stream(output)).transpose()?;
+            Ok((output.clone(), recording))
+        })
+        .collect::<Result<Vec<_>>>()
+        .and_then(|outputs| match context.args.wall.is_some() {
+            // the tiles of a wall show one picture, so they must not drift apart
+            true => OutputSender::connect_lockstep(outputs, pool),
+            false => outputs
+                .into_iter()
+                .map(|(output, recording)| OutputSender::connect(output, Arc::clone(&pool), recording))
+                .collect(),
+        });
+    context.senders = senders.unwrap_or_else(|e| {
+        restore_terminal(context);
+        eprintln!("{} {}", "::".red(), e);
+        std::process::exit(1);
+    });
+    let hosts = context.outputs.iter().map(|o| o.host.as_str()).collect::<Vec<_>>();
+    notify(context, format!("Playing video on {}", hosts.join(", ")));
+}
 
-    let cache_key = args.clone().into();
+fn build_compressor(config: &Config, context: &Context) -> Result<TiledCompressor> {
+    let compression_level = 
+        CompressionLevelArg::try_from(context.args.compression_level.clone())
+            .map_err(|e| Error::InvalidArgs(e.to_string()))?
+            .per_frame(context.metadata.fps);
 
-    if !is_cache_valid(&cache_key).unwrap_or(false) || args.nocache {
-        clean_cache()?;
+    let compressor = VideoCompressor::new(
+        context.args.compression_algorithm.clone(),
+        compression_level,
+        &CompressorOptions::from(&context.args),
+    )?;
 
-        let metadata = extract_video_frames(
-            &args.input,
-            args.fps.unwrap_or(get_video_framerate(&args.input).await?),
+    // every tile of a wall is compressed separately, with its own budget
+    let compressor = match &context.args.wall {
+        Some(name) => {
+            let first = context.compositor.load_frame(1)?;
+            let wall = &config.walls[name];
+            wall.validate(first.width(), first.height())?;
+            TiledCompressor::regions(compressor, wall.tiles.iter().map(|t| t.region))
+        }
+        None => TiledCompressor::whole(compressor),
+    };
+    Ok(compressor)
+}
+
+/// How a prepared video is played
+enum Playback {
+    JustInTime(TiledCompressor),
+    AheadOfTime(AotFrames, MetricsRecorder),
+}
+
+/// A video (or playlist entry) that is ready to be played
+struct PreparedEntry {
+    context: Context,
+    playback: Playback,
+}
+
+/// Where the previous playlist entry left off
+struct TransitionFrom {
+    transition: Transition,
+    /// Length of the transition (in s)
+    duration: f64,
+    /// Last frame of the previous entry
+    frame: Frame,
+    /// Offset the previous entry was shown at
+    offset: (usize, usize),
+}
+
+/// Extracts the frames of `args.input[0]` into `cache` and, when compressing ahead-of-time,
+/// compresses them. Without `progress`, nothing is printed, so this can run in the background
+/// while another entry plays.
+async fn prepare_entry(
+    config: Arc<Config>,
+    args: Args,
+    cache: FrameCache,
+    thread_pool: Arc<ThreadPool>,
+    control: Option<Arc<ControlServer>>,
+    previous: Option<TransitionFrom>,
+    progress: bool,
+) -> Result<PreparedEntry> {
+    let outputs = resolve_outputs(&config, &args)?;
+    let layers = find_layers(&config, &args)?;
+    let texts = find_texts(&config, &args)?;
+    let subtitles = args.subtitles.as_deref().map(Subtitles::load).transpose()?;
+
+    let input = &args.input[0];
+    let metadata = cache
+        .prepare(
+            &args.cache_key(input),
+            input,
+            args.fps,
             args.width.unwrap_or(-1),
             args.height.unwrap_or(-1),
+            &args.video_filters(),
+            args.nocache,
+            progress,
         )
         .await?;
-
-        metadata.write()?;
-
-        write_cache_id(&cache_key)?;
+    let audio = match args.audio {
+        Some(_) => cache.prepare_audio(input).await?.map(Arc::new),
+        None => None,
+    };
+    if args.audio.is_some() && audio.is_none() && progress {
+        println!("{} {} has no audio track", "::".yellow(), input);
+    }
+    let mut compositor = Compositor::prepare(cache, layers, metadata.fps, args.nocache, progress).await?;
+    if !texts.is_empty() || subtitles.is_some() {
+        compositor = compositor.with_text(TextRenderer {
+            overlays: texts,
+            subtitles,
+            subtitle_scale: args.subtitle_scale,
+            input: input.clone(),
+            fps: metadata.fps,
+            frame_count: metadata.frame_count,
+        });
     }
 
-    let metadata = VideoMetadata::load()?;
+    if let Some(previous) = previous {
+        let first = compositor.load_frame(1)?;
+        let from = reframe(
+            &previous.frame,
+            previous.offset,
+            (args.x_offset, args.y_offset),
+            first.width(),
+            first.height(),
+        );
+        let frames = (previous.duration * metadata.fps).round() as usize;
+        compositor = compositor.with_transition(previous.transition, frames, from);
+    }
 
-    let thread_pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(args.send_threads)
-        .build()
-        .unwrap();
+    let mut outputs = outputs;
+    fit_outputs(&mut outputs, &compositor)?;
+    let quantizer = args.quantizer()?;
 
-    let mut context = Context {
+    let context = Context {
+        config: Arc::clone(&config),
         args,
-        stream: None,
+        outputs,
+        senders: Vec::new(),
         metadata,
+        compositor: Arc::new(compositor),
         thread_pool,
+        quantizer,
+        control,
+        tui: None,
+        preview: None,
+        recorder: None,
+        audio,
+        audio_output: None,
     };
-    
-    let mut compression_level = 
-        CompressionLevelArg::try_from(context.args.compression_level.clone())
-            .map_err(|e| Error::InvalidArgs(e.to_string()))?;
-    
-    if let CompressionLevelArg::Number(n) = compression_level {
-        compression_level = CompressionLevelArg::Number(
-            ((n * 1024) as f64 / context.metadata.fps) as usize
-        ); 
+    let compressor = build_compressor(&config, &context)?;
+
+    let playback = match context.args.jit {
+        true => Playback::JustInTime(compressor),
+        false => {
+            let (frames, metrics) = tokio::task::block_in_place(|| {
+                compress_frames_ahead_of_time(&context, compressor, progress)
+            })?;
+            Playback::AheadOfTime(frames, metrics)
+        }
+    };
+
+    Ok(PreparedEntry { context, playback })
+}
+
+/// Plays a prepared entry from frame `start`, once or forever with `repeat`
+fn play_entry(context: &mut Context, playback: &Playback, repeat: bool, start: usize) -> Result<LoopExit> {
+    match playback {
+        Playback::JustInTime(compressor) => loop_just_in_time(context, compressor, repeat, start),
+        Playback::AheadOfTime(frames, metrics) => {
+            report_metrics(context, metrics)?;
+            loop_ahead_of_time(context, frames, metrics, repeat, start)
+        }
     }
+}
 
-    let compressor = VideoCompressor::new(
-        context.args.compression_algorithm.clone(),
-        compression_level,
-        context.args.debug
-    )?;
+/// Renders what the wall would show on every frame into a video file
+fn export(context: &Context, playback: &Playback, path: &str) -> Result<()> {
+    let frame_count = context.metadata.frame_count;
+    println!("{} Exporting to {} ...", "::".blue(), path);
+    let counter = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let progress = progress_tracker(counter.clone(), frame_count, "frames exported".to_string());
 
-    let host = context.args.host.clone().unwrap();
-    
-    if context.args.jit {
-        context.stream = Some(connect(&host));
-        println!("{} Playing video on {}", "::".blue(), host);
-        loop_just_in_time(&context, compressor)?;
-    } else {
-        let frame_data_vec = compress_frames_to_vec(&context, compressor).unwrap_or_else(|e| {
-            eprintln!("Error: {:?}", e);
-            std::process::exit(1);
-        });
+    let mut canvas: Option<Frame> = None;
+    let mut encoder: Option<VideoEncoder> = None;
+    let mut export_frame = |frame_data: &FrameData| -> Result<()> {
+        let canvas = match &mut canvas {
+            Some(canvas) => {
+                canvas.apply_frame_data_mut(frame_data);
+                canvas
+            }
+            None => canvas.insert(Frame::from(frame_data.clone())),
+        };
+        let encoder = match &mut encoder {
+            Some(encoder) => encoder,
+            None => encoder.insert(VideoEncoder::spawn(
+                path,
+                canvas.width(),
+                canvas.height(),
+                context.metadata.fps,
+            )?),
+        };
+        encoder.write(canvas)?;
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    };
 
-        context.stream = Some(connect(&host));
-        println!("{} Playing video on {}", "::".blue(), host);
-        loop_ahead_of_time(&context, frame_data_vec)?;
+    match playback {
+        Playback::JustInTime(compressor) => {
+            let pipeline = JitPipeline::spawn(
+                frame_count,
+                1,
+                Arc::clone(&context.compositor),
+                context.args.jit_lookahead.unwrap_or(DEFAULT_JIT_LOOKAHEAD),
+                compressor.clone(),
+                context.quantizer.clone(),
+                false,
+            );
+            for _ in 0..frame_count {
+                export_frame(&pipeline.recv()?.frame_data)?;
+            }
+        }
+        Playback::AheadOfTime(AotFrames::Memory(frames), _) => {
+            frames.iter().try_for_each(&mut export_frame)?;
+        }
+        Playback::AheadOfTime(AotFrames::Spilled(spill), _) => {
+            let reader = spill.stream()?;
+            for _ in 0..spill.frame_count() {
+                export_frame(&reader.recv()?)?;
+            }
+        }
     }
 
+    if let Some(encoder) = &mut encoder {
+        encoder.finish()?;
+    }
+    progress.join().unwrap();
+    println!("{} Exported {}", "::".blue(), path);
     Ok(())
 }
+
+async fn play(config: Arc<Config>, args: Args) -> Result<()> {
+    let playlist = Playlist::from_args(&args)?;
+    for entry in &playlist.entries {
+        verify_input(&entry.input)?;
+    }
+    if args.export.is_some() && playlist.entries.len() > 1 {
+        return Err(Error::InvalidArgs("--export takes a single input".to_string()));
+    }
+
+    let thread_pool = Arc::new(
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.send_threads)
+            .build()
+            .unwrap()
+    );
+    let control = args.control
+        .as_ref()
+        .map(|path| ControlServer::bind(path.as_ref()).map(Arc::new))
+        .transpose()?;
+
+    // a single video keeps using the primary frame cache and is looped like before, while every
+    // playlist entry gets a cache of its own so the next entry can be prepared during playback
+    let single = playlist.entries.len() == 1;
+    let loop_forever = single && playlist.repeat;
+    let mut extracted = HashSet::new();
+    let mut entry_args = |i: usize| {
+        let mut entry_args = playlist.entries[i].args(&args);
+        // with --nocache, every entry is extracted again once, not every time it plays
+        entry_args.nocache &= extracted.insert(i);
+        let cache = match single {
+            true => FrameCache::primary(),
+            false => FrameCache::input(&entry_args.cache_key(&entry_args.input[0])),
+        };
+        (entry_args, cache)
+    };
+
+    let mut order = playlist.order();
+    let (first_args, first_cache) = entry_args(order.next().expect("playlist is not empty"));
+    let PreparedEntry { mut context, mut playback } = prepare_entry(
+        Arc::clone(&config),
+        first_args,
+        first_cache,
+        Arc::clone(&thread_pool),
+        control.clone(),
+        None,
+        true,
+    ).await?;
+    if let Some(path) = &args.export {
+        return tokio::task::block_in_place(|| export(&context, &playback, path));
+    }
+    context.recorder = args.record
+        .as_ref()
+        .map(|path| Recorder::create(path.as_ref()).map(Arc::new))
+        .transpose()?;
+    connect_outputs(&mut context, None);
+    context.tui = args.tui.then(Tui::start).transpose()?.map(|tui| Arc::new(Mutex::new(tui)));
+    context.audio_output = args.audio
+        .as_ref()
+        .map(|target| {
+            let player = args.audio_player.as_deref().unwrap_or(DEFAULT_AUDIO_PLAYER);
+            AudioOutput::open(target, player, args.av_offset).map(|a| Arc::new(Mutex::new(a)))
+        })
+        .transpose()?;
+    context.preview = (args.dry_run && !args.tui)
+        .then(Preview::new)
+        .transpose()?
+        .map(|preview| Arc::new(Mutex::new(preview)));
+
+    loop {
+        let next = match loop_forever {
+            true => None,
+            false => order.next(),
+        };
+
+        // prepare the next entry while this one plays
+        let next = match next {
+            Some(i) => {
+                let (next_args, next_cache) = entry_args(i);
+                let previous = TransitionFrom {
+                    transition: playlist.transition.unwrap_or_default(),
+                    duration: playlist.transition_duration.unwrap_or(DEFAULT_TRANSITION_DURATION),
+                    frame: context.compositor.load_frame(context.metadata.frame_count)?,
+                    offset: (context.args.x_offset, context.args.y_offset),
+                };
+                Some(tokio::spawn(prepare_entry(
+                    Arc::clone(&config),
+                    next_args,
+                    next_cache,
+                    Arc::clone(&thread_pool),
+                    control.clone(),
+                    Some(previous),
+                    false,
+                )))
+            }
+            None => None,
+        };
+
+        if !single {
+            notify(&context, format!("Playing {}", context.args.input[0]));
+        }
+        let mut start = 1;
+        // reloads replace the current entry and continue where it left off
+        while let LoopExit::Reload { mut args, frame } =
+            tokio::task::block_in_place(|| play_entry(&mut context, &playback, loop_forever, start))?
+        {
+            args.nocache = false;
+            let cache = match args.input == context.args.input {
+                true => context.compositor.base().clone(),
+                false => FrameCache::input(&args.cache_key(&args.input[0])),
+            };
+            let reloaded = prepare_entry(
+                Arc::clone(&config),
+                *args,
+                cache,
+                Arc::clone(&thread_pool),
+                control.clone(),
+                None,
+                context.tui.is_none(),
+            ).await;
+            match reloaded {
+                Ok(PreparedEntry { context: mut new_context, playback: new_playback }) => {
+                    inherit(&mut new_context, &context);
+                    connect_outputs(&mut new_context, Some(&mut context));
+                    (context, playback) = (new_context, new_playback);
+                }
+                Err(e) if context.tui.is_some() => notify(&context, e.to_string()),
+                Err(e) => eprintln!("{} {}", "::".red(), e),
+            }
+            start = frame.clamp(1, context.metadata.frame_count.max(1));
+        }
+
+        let Some(next) = next else { return Ok(()) };
+        let PreparedEntry { context: mut next_context, playback: next_playback } = next
+            .await
+            .map_err(|e| Error::Custom(format!("Failed to prepare the next video: {}", e)))??;
+        inherit(&mut next_context, &context);
+        connect_outputs(&mut next_context, Some(&mut context));
+        (context, playback) = (next_context, next_playback);
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {    
+    let config = Config::load().unwrap_or_else(
+        |e| {
+            eprintln!("Failed to load config:\n{}", e.to_string().red());
+            eprintln!(
+                "Edit the config file at [{}] to fix the problem.", 
+                paths::config_file().to_str().unwrap().cyan()
+            );
+            eprintln!(
+                "If you recently updated bad-apple-flut, you may need to add missing fields to the config file. See the latest README for details."
+            );            
+            std::process::exit(1);
+        }
+    );
+
+    let mut cli = Cli::parse();
+    let args = config.args.clone().merge(&mut cli.args);
+
+    match cli.command {
+        Some(Command::Analyze { levels, args: mut analyze_args }) => {
+            let args = args.merge(&mut analyze_args);
+            verify_args(&args)?;
+            analyze(&args, &levels).await
+        }
+        Some(Command::Replay { recording, fast, args: mut replay_args }) => {
+            let args = args.merge(&mut replay_args);
+            replay_recording(&config, &args, &recording, fast)
+        }
+        None => {
+            verify_args(&args)?;
+            play(Arc::new(config), args).await
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt::Display;
+use std::io::Write as _;
+use std::path::Path;
+
+use rayon::prelude::*;
+
+use crate::{frame::Frame, Color, Result};
+
+/// Side length of the (non-overlapping) windows SSIM is computed over
+const SSIM_WINDOW: usize = 8;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+#[inline]
+fn luma(c: &Color) -> f64 {
+    c.r as f64 * 0.299 + c.g as f64 * 0.587 + c.b as f64 * 0.114
+}
+
+/// Mean squared error over all RGB channels
+pub fn mse(reference: &Frame, distorted: &Frame) -> f64 {
+    assert_eq!(reference.width(), distorted.width(), "frame widths differ");
+    assert_eq!(reference.height(), distorted.height(), "frame heights differ");
+
+    if reference.data().is_empty() {
+        return 0.0;
+    }
+
+    let sum: u64 = reference.data().par_iter()
+        .zip(distorted.data().par_iter())
+        .map(|(a, b)| {
+            let dr = a.r.abs_diff(b.r) as u64;
+            let dg = a.g.abs_diff(b.g) as u64;
+            let db = a.b.abs_diff(b.b) as u64;
+            dr * dr + dg * dg + db * db
+        })
+        .sum();
+
+    sum as f64 / (reference.data().len() * 3) as f64
+}
+
+/// Converts a mean squared error to PSNR (in dB). Lossless frames yield infinity.
+pub fn mse_to_psnr(mse: f64) -> f64 {
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+}
+
+/// Peak signal-to-noise ratio (in dB) over all RGB channels
+pub fn psnr(reference: &Frame, distorted: &Frame) -> f64 {
+    mse_to_psnr(mse(reference, distorted))
+}
+
+/// Structural similarity of the luma channel, averaged over 8x8 windows
+// https://en.wikipedia.org/wiki/Structural_similarity_index_measure
+pub fn ssim(reference: &Frame, distorted: &Frame) -> f64 {
+    assert_eq!(reference.width(), distorted.width(), "frame widths differ");
+    assert_eq!(reference.height(), distorted.height(), "frame heights differ");
+
+    let width = reference.width();
+    let height = reference.height();
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+
+    let windows_x = width.div_ceil(SSIM_WINDOW);
+    let windows_y = height.div_ceil(SSIM_WINDOW);
+
+    let sum: f64 = (0..windows_x * windows_y)
+        .into_par_iter()
+        .map(|w| {
+            let x0 = (w % windows_x) * SSIM_WINDOW;
+            let y0 = (w / windows_x) * SSIM_WINDOW;
+            let x1 = (x0 + SSIM_WINDOW).min(width);
+            let y1 = (y0 + SSIM_WINDOW).min(height);
+
+            let (mut sx, mut sy, mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let i = y * width + x;
+                    let a = luma(&reference.data()[i]);
+                    let b = luma(&distorted.data()[i]);
+                    sx += a; sy += b;
+                    sxx += a * a; syy += b * b; sxy += a * b;
+                }
+            }
+
+            let n = ((x1 - x0) * (y1 - y0)) as f64;
+            let mu_x = sx / n;
+            let mu_y = sy / n;
+            let var_x = sxx / n - mu_x * mu_x;
+            let var_y = syy / n - mu_y * mu_y;
+            let cov = sxy / n - mu_x * mu_y;
+
+            ((2.0 * mu_x * mu_y + SSIM_C1) * (2.0 * cov + SSIM_C2))
+                / ((mu_x * mu_x + mu_y * mu_y + SSIM_C1) * (var_x + var_y + SSIM_C2))
+        })
+        .sum();
+
+    sum / (windows_x * windows_y) as f64
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+/// Quality metrics of a single frame, comparing the source frame to the reconstructed canvas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameMetrics {
+    pub frame: usize,
+    pub pixels: usize,
+    pub mse: f64,
+    pub ssim: f64,
+}
+
+impl FrameMetrics {
+    pub fn compute(frame: usize, pixels: usize, source: &Frame, canvas: &Frame) -> Self {
+        Self {
+            frame,
+            pixels,
+            mse: mse(source, canvas),
+            ssim: ssim(source, canvas),
+        }
+    }
+    #[inline] pub fn psnr(&self) -> f64 { mse_to_psnr(self.mse) }
+}
+
+impl Display for FrameMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame {} | {} px | PSNR {:.2} dB | SSIM {:.4}",
+            self.frame, self.pixels, self.psnr(), self.ssim
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricsSummary {
+    pub frames: usize,
+    pub mean_pixels: f64,
+    /// PSNR of the mean squared error over all frames
+    pub psnr: f64,
+    pub min_psnr: f64,
+    pub mean_ssim: f64,
+    pub min_ssim: f64,
+}
+
+impl Display for MetricsSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "frames:      {}", self.frames)?;
+        writeln!(f, "pixels/frame {:.1}", self.mean_pixels)?;
+        writeln!(f, "PSNR:        {:.2} dB (min {:.2} dB)", self.psnr, self.min_psnr)?;
+        write!(f, "SSIM:        {:.4} (min {:.4})", self.mean_ssim, self.min_ssim)
+    }
+}
+
+/// Collects per-frame metrics over a run
+#[derive(Debug, Clone, Default)]
+pub struct MetricsRecorder {
+    frames: Vec<FrameMetrics>,
+}
+
+impl MetricsRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames are expected to be recorded in order, use [`MetricsRecorder::extend`] otherwise
+    pub fn record(&mut self, metrics: FrameMetrics) {
+        self.frames.push(metrics);
+    }
+    pub fn extend(&mut self, metrics: impl IntoIterator<Item = FrameMetrics>) {
+        self.frames.extend(metrics);
+        self.frames.sort_unstable_by_key(|m| m.frame);
+    }
+
+    #[inline] pub fn frames(&self) -> &[FrameMetrics] { &self.frames }
+    #[inline] pub fn last(&self) -> Option<&FrameMetrics> { self.frames.last() }
+    pub fn get(&self, frame: usize) -> Option<&FrameMetrics> {
+        self.frames
+            .binary_search_by_key(&frame, |m| m.frame)
+            .ok()
+            .map(|i| &self.frames[i])
+    }
+
+    pub fn summary(&self) -> Option<MetricsSummary> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        let n = self.frames.len() as f64;
+
+        let mean_mse = self.frames.iter().map(|m| m.mse).sum::<f64>() / n;
+        let max_mse = self.frames.iter().map(|m| m.mse).fold(0.0, f64::max);
+
+        Some(MetricsSummary {
+            frames: self.frames.len(),
+            mean_pixels: self.frames.iter().map(|m| m.pixels as f64).sum::<f64>() / n,
+            psnr: mse_to_psnr(mean_mse),
+            min_psnr: mse_to_psnr(max_mse),
+            mean_ssim: self.frames.iter().map(|m| m.ssim).sum::<f64>() / n,
+            min_ssim: self.frames.iter().map(|m| m.ssim).fold(1.0, f64::min),
+        })
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(out, "frame,pixels,mse,psnr,ssim")?;
+        for m in &self.frames {
+            writeln!(out, "{},{},{:.4},{:.4},{:.6}", m.frame, m.pixels, m.mse, m.psnr(), m.ssim)?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: usize, height: usize, c: Color) -> Frame {
+        Frame::new(width, height, vec![c; width * height])
+    }
+
+    #[test]
+    fn test_identical_frames() {
+        let a = Frame::debug(13, 7);
+        assert_eq!(mse(&a, &a), 0.0);
+        assert_eq!(psnr(&a, &a), f64::INFINITY);
+        assert!((ssim(&a, &a) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_psnr_known_error() {
+        // every channel off by 16 -> mse = 256
+        let a = solid(4, 4, Color::new(100, 100, 100));
+        let b = solid(4, 4, Color::new(116, 116, 116));
+        assert_eq!(mse(&a, &b), 256.0);
+        assert!((psnr(&a, &b) - 24.0484).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_ssim_penalizes_structure_loss() {
+        let mut data = vec![Color::new(0, 0, 0); 16 * 16];
+        for (i, c) in data.iter_mut().enumerate() {
+            if (i % 16 + i / 16) % 2 == 0 { *c = Color::new(255, 255, 255); }
+        }
+        let checker = Frame::new(16, 16, data);
+        let flat = solid(16, 16, Color::new(128, 128, 128));
+        assert!(ssim(&checker, &flat) < 0.1);
+    }
+}
@@ -0,0 +1,164 @@
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{frame::{Frame, FrameData}, ColorDistance, Pixel};
+
+/// Order in which the pixels of a frame are sent. If a frame doesn't finish sending in time, the
+/// order determines what the partial frame looks like on the wall.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PixelOrder {
+    /// As the compressor emits them; v2 sends the pixels that changed the most first
+    #[default]
+    Compressor,
+    /// Top to bottom, left to right. Partial frames show up as a tear.
+    Scanline,
+    /// Random shuffle. Partial frames look like noise.
+    Random,
+    /// Along a Hilbert curve. Partial frames are updated in compact blocks.
+    Hilbert,
+    /// Rows in bit-reversed order (0, 1/2, 1/4, 3/4, ...). Partial frames look interlaced.
+    Interleaved,
+    /// Largest color error first
+    Priority,
+}
+
+/// Sorts the pixels of every frame according to a [`PixelOrder`]
+#[derive(Debug, Clone)]
+pub struct PixelOrderer {
+    order: PixelOrder,
+    distance: ColorDistance,
+    rng: XorShift64,
+    /// What the wall currently shows, only tracked for [`PixelOrder::Priority`]
+    canvas: Option<Frame>,
+}
+
+impl PixelOrderer {
+    pub fn new(order: PixelOrder, distance: ColorDistance) -> Self {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Self {
+            order,
+            distance,
+            rng: XorShift64::new(seed),
+            canvas: None,
+        }
+    }
+
+    #[inline] pub fn order(&self) -> PixelOrder { self.order }
+
+    /// Returns the pixels of a frame in send order
+    pub fn pixels(&mut self, frame_data: &FrameData) -> Vec<Pixel> {
+        let mut pixels = frame_data.clone().to_pixels();
+
+        match self.order {
+            PixelOrder::Compressor => {}
+            PixelOrder::Scanline => {
+                pixels.par_sort_unstable_by_key(|p| (p.y, p.x));
+            }
+            PixelOrder::Random => {
+                self.rng.shuffle(&mut pixels);
+            }
+            PixelOrder::Hilbert => {
+                let size = pixels.iter().map(|p| p.x().max(p.y())).max().unwrap_or(0) + 1;
+                let n = size.next_power_of_two();
+                pixels.par_sort_by_cached_key(|p| hilbert_index(n, p.x(), p.y()));
+            }
+            PixelOrder::Interleaved => {
+                pixels.par_sort_unstable_by_key(|p| (p.y.reverse_bits(), p.x));
+            }
+            PixelOrder::Priority => {
+                match &self.canvas {
+                    Some(canvas) if !matches!(frame_data, FrameData::Full { .. }) => {
+                        pixels.par_sort_by_cached_key(|p| {
+                            let old = &canvas.data()[p.index(canvas.width())];
+                            std::cmp::Reverse(self.distance.distance(old, &p.color).to_bits())
+                        });
+                    }
+                    // no previous state to compare against
+                    _ => pixels.par_sort_unstable_by_key(|p| (p.y, p.x)),
+                }
+                self.canvas = Some(match self.canvas.take() {
+                    Some(canvas) => canvas.apply_frame_data(frame_data),
+                    None => Frame::from(frame_data.clone()),
+                });
+            }
+        }
+        pixels
+    }
+}
+
+/// Position of (x, y) along a Hilbert curve filling an n*n square (n must be a power of two)
+// https://en.wikipedia.org/wiki/Hilbert_curve#Applications_and_mapping_algorithms
+pub fn hilbert_index(n: usize, mut x: usize, mut y: usize) -> usize {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as usize;
+        let ry = (y & s > 0) as usize;
+        d += s * s * ((3 * rx) ^ ry);
+
+        // rotate quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x &= s - 1;
+        y &= s - 1;
+        s /= 2;
+    }
+    d
+}
+
+/// Small, fast PRNG for shuffling pixels (and playlists)
+// https://en.wikipedia.org/wiki/Xorshift#xorshift*
+#[derive(Debug, Clone)]
+pub(crate) struct XorShift64 {
+    state: u64,
+}
+
+impl XorShift64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed | 1 }
+    }
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+    pub(crate) fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            slice.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hilbert_index_is_continuous() {
+        let n = 16;
+        let mut points = vec![(0, 0); n * n];
+        for x in 0..n {
+            for y in 0..n {
+                points[hilbert_index(n, x, y)] = (x, y);
+            }
+        }
+        // consecutive points along the curve are neighbours
+        for w in points.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1, "{:?} -> {:?}", a, b);
+        }
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use rayon::{prelude::*, ThreadPool};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{pixels_to_cmds, Error, Flip, Pixel, Protocol, RecordedStream, Region, Result, Rotation};
+
+/// Number of pixels encoded per parallel task
+const CHUNK_SIZE: usize = 400;
+
+/// A server the video (or a tile of a wall) is played on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub host: String,
+    pub protocol: Protocol,
+    pub canvas: u8,
+    pub x_offset: usize,
+    pub y_offset: usize,
+    /// Part of the frame shown on this output, the whole frame if `None`
+    pub region: Option<Region>,
+    pub transform: OutputTransform,
+}
+
+impl Output {
+    /// Gives a transformed output that shows the whole frame a region of that size, which the
+    /// transform needs to place pixels
+    pub fn fit_frame(&mut self, width: usize, height: usize) {
+        if !self.transform.is_identity() && self.region.is_none() {
+            self.region = Some(Region { x: 0, y: 0, width, height });
+        }
+    }
+
+    /// Size of the part of the frame shown on this output, `(0, 0)` if it shows the whole frame
+    /// and has no transform
+    fn size(&self) -> (usize, usize) {
+        self.region.map(|r| (r.width, r.height)).unwrap_or_default()
+    }
+
+    /// Selects the pixels shown on this output, in output coordinates
+    pub fn pixels(&self, pixels: &[Pixel]) -> Vec<Pixel> {
+        match &self.region {
+            None => pixels.to_vec(),
+            Some(r) => pixels
+                .iter()
+                .filter(|p| r.contains(p.x(), p.y()))
+                .map(|p| Pixel::new(p.x() - r.x, p.y() - r.y, p.color))
+                .collect(),
+        }
+    }
+}
+
+/// How an output is mounted: its pixels are rotated clockwise, then mirrored (as seen on the
+/// canvas), then scaled up by an integer factor, every pixel becoming a `scale`x`scale` block.
+/// Applied when encoding, so the compressed frames are shared by all outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OutputTransform {
+    pub rotate: Option<Rotation>,
+    pub flip: Option<Flip>,
+    #[serde(default = "default_scale")]
+    pub scale: usize,
+}
+
+fn default_scale() -> usize {
+    1
+}
+
+impl Default for OutputTransform {
+    fn default() -> Self {
+        Self { rotate: None, flip: None, scale: default_scale() }
+    }
+}
+
+impl OutputTransform {
+    pub fn is_identity(&self) -> bool {
+        self.rotate.is_none() && self.flip.is_none() && self.scale <= 1
+    }
+
+    /// Position on the canvas (before the offset) of the top left corner of pixel `x`, `y` of
+    /// a `width`x`height` frame
+    #[inline]
+    pub fn place(&self, x: usize, y: usize, (width, height): (usize, usize)) -> (usize, usize) {
+        let (x, y, width, height) = match self.rotate {
+            None => (x, y, width, height),
+            Some(Rotation::Rotate90) => (height - 1 - y, x, height, width),
+            Some(Rotation::Rotate180) => (width - 1 - x, height - 1 - y, width, height),
+            Some(Rotation::Rotate270) => (y, width - 1 - x, height, width),
+        };
+        let (x, y) = match self.flip {
+            None => (x, y),
+            Some(Flip::Horizontal) => (width - 1 - x, y),
+            Some(Flip::Vertical) => (x, height - 1 - y),
+            Some(Flip::Both) => (width - 1 - x, height - 1 - y),
+        };
+        (x * self.scale.max(1), y * self.scale.max(1))
+    }
+}
+
+#[derive(Default)]
+struct Mailbox {
+    /// Pixels waiting to be sent
+    pending: Option<Vec<Pixel>>,
+    /// Number of the newest frame merged into `pending`
+    pending_frame: u64,
+    /// Number of the frame being written to the server, if any
+    in_flight: Option<u64>,
+    /// Number of frames queued so far, including empty ones
+    frames: u64,
+    /// Number of frames that were merged into a later frame
+    coalesced: usize,
+    /// Number of bytes written to the server
+    bytes_sent: usize,
+    /// Time spent encoding and writing frames
+    busy: Duration,
+    error: Option<String>,
+    closed: bool,
+}
+
+/// Mailboxes of outputs that share a frame clock, see [`OutputSender::connect_lockstep`]
+type Mailboxes = Arc<(Mutex<Vec<Mailbox>>, Condvar)>;
+
+/// Whether the output with mailbox `i` may start sending its pending frame. Outputs in lockstep
+/// start a frame only once no other output is still sending, or waiting to send, an older frame.
+/// Frames that arrive in the meantime are merged on all of them, so they skip frames together.
+fn may_start(mailboxes: &[Mailbox], i: usize) -> bool {
+    let frame = mailboxes[i].pending_frame;
+    mailboxes.iter().enumerate().filter(|(j, m)| *j != i && m.error.is_none()).all(|(_, m)| {
+        m.in_flight.is_none_or(|f| f == frame) && (m.pending.is_none() || m.pending_frame >= frame)
+    })
+}
+
+/// Connection to an [`Output`] with its own sender thread. A frame that arrives while the previous
+/// frame is still being sent is merged into the frame waiting to be sent, so a slow server skips
+/// frames instead of stalling the other outputs.
+pub struct OutputSender {
+    output: Output,
+    mailboxes: Mailboxes,
+    /// Index of the mailbox of this output
+    index: usize,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl OutputSender {
+    /// Connects to the output. Commands are encoded on `pool` and everything sent is written to
+    /// `recording`, if given.
+    pub fn connect(output: Output, pool: Arc<ThreadPool>, recording: Option<RecordedStream>) -> Result<Self> {
+        let mut senders = Self::connect_lockstep(vec![(output, recording)], pool)?;
+        Ok(senders.remove(0))
+    }
+
+    /// Connects to outputs that show parts of the same picture (the tiles of a wall). They share a
+    /// frame clock: every output sends the same frame at the same time, and a slow output holds
+    /// back the others instead of falling behind them.
+    pub fn connect_lockstep(
+        outputs: Vec<(Output, Option<RecordedStream>)>,
+        pool: Arc<ThreadPool>,
+    ) -> Result<Vec<Self>> {
+        let streams = outputs
+            .iter()
+            .map(|(output, _)| {
+                TcpStream::connect(&output.host)
+                    .map_err(|e| Error::Custom(format!("Failed to connect to {}: {}", output.host, e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mailboxes: Mailboxes = Arc::new((
+            Mutex::new(outputs.iter().map(|_| Mailbox::default()).collect()),
+            Condvar::new(),
+        ));
+        let senders = outputs
+            .into_iter()
+            .zip(streams)
+            .enumerate()
+            .map(|(index, ((output, recording), stream))| {
+                let handle = {
+                    let output = output.clone();
+                    let mailboxes = Arc::clone(&mailboxes);
+                    let pool = Arc::clone(&pool);
+                    thread::spawn(move || sender_thread(output, stream, pool, mailboxes, index, recording))
+                };
+                Self { output, mailboxes: Arc::clone(&mailboxes), index, handle: Some(handle) }
+            })
+            .collect();
+        Ok(senders)
+    }
+
+    #[inline] pub fn output(&self) -> &Output { &self.output }
+
+    pub fn coalesced(&self) -> usize {
+        self.mailboxes.0.lock().unwrap()[self.index].coalesced
+    }
+    pub fn bytes_sent(&self) -> usize {
+        self.mailboxes.0.lock().unwrap()[self.index].bytes_sent
+    }
+    /// Time the sender thread spent encoding and writing frames, including the time a slow
+    /// server took to accept them
+    pub fn busy(&self) -> Duration {
+        self.mailboxes.0.lock().unwrap()[self.index].busy
+    }
+
+    /// Queues the pixels of a frame (in source coordinates) without waiting for them to be sent.
+    /// Fails if sending an earlier frame failed. Outputs in lockstep must get every frame, even
+    /// if it has no pixels for them.
+    pub fn send(&self, pixels: &[Pixel]) -> Result<()> {
+        let pixels = self.output.pixels(pixels);
+
+        let (lock, cvar) = &*self.mailboxes;
+        let mut mailboxes = lock.lock().unwrap();
+        let mailbox = &mut mailboxes[self.index];
+        if let Some(e) = &mailbox.error {
+            return Err(Error::Custom(e.clone()));
+        }
+        mailbox.frames += 1;
+        if pixels.is_empty() {
+            return Ok(());
+        }
+        match &mut mailbox.pending {
+            Some(pending) => {
+                merge_pixels(pending, pixels);
+                mailbox.coalesced += 1;
+            }
+            None => mailbox.pending = Some(pixels),
+        }
+        mailbox.pending_frame = mailbox.frames;
+        cvar.notify_all();
+        Ok(())
+    }
+}
+
+impl Drop for OutputSender {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.mailboxes;
+        lock.lock().unwrap()[self.index].closed = true;
+        cvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap_or(());
+        }
+    }
+}
+
+fn sender_thread(
+    output: Output,
+    mut stream: TcpStream,
+    pool: Arc<ThreadPool>,
+    mailboxes: Mailboxes,
+    index: usize,
+    recording: Option<RecordedStream>,
+) {
+    let (lock, cvar) = &*mailboxes;
+    let finish = |update: &dyn Fn(&mut Mailbox)| {
+        let mut mailboxes = lock.lock().unwrap();
+        mailboxes[index].in_flight = None;
+        update(&mut mailboxes[index]);
+        // outputs in lockstep may be waiting for this one
+        cvar.notify_all();
+    };
+    loop {
+        let pixels = {
+            let mut mailboxes = cvar
+                .wait_while(lock.lock().unwrap(), |m| {
+                    !m[index].closed && (m[index].pending.is_none() || !may_start(m, index))
+                })
+                .unwrap();
+            let mailbox = &mut mailboxes[index];
+            match mailbox.pending.take() {
+                Some(pixels) => {
+                    mailbox.in_flight = Some(mailbox.pending_frame);
+                    pixels
+                }
+                None => return, // closed
+            }
+        };
+
+        let start = Instant::now();
+        let msgs = pool.install(|| {
+            pixels
+                .par_chunks(CHUNK_SIZE)
+                .map(|chunk| {
+                    pixels_to_cmds(
+                        output.protocol,
+                        output.canvas,
+                        chunk,
+                        output.x_offset,
+                        output.y_offset,
+                        &output.transform,
+                        output.size(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let result = msgs
+            .iter()
+            .try_for_each(|msg| stream.write_all(msg))
+            .and_then(|_| stream.flush());
+
+        let recorded = match (&result, &recording) {
+            (Ok(_), Some(recording)) => recording.record(&msgs),
+            _ => Ok(()),
+        };
+        if let Err(e) = recorded {
+            finish(&|m| m.error = Some(format!("Unable to record frame: {}", e)));
+            return;
+        }
+        if let Err(e) = result {
+            let e = match e.kind() {
+                std::io::ErrorKind::BrokenPipe => format!(
+                    "Unable to send frame to {}: Connection closed by server",
+                    output.host
+                ),
+                _ => format!("Unable to send frame to {}: {}", output.host, e),
+            };
+            finish(&|m| m.error = Some(e.clone()));
+            return;
+        }
+        finish(&|m| {
+            m.bytes_sent += msgs.iter().map(Vec::len).sum::<usize>();
+            m.busy += start.elapsed();
+        });
+    }
+}
+
+/// Merges the pixels of a newer frame into `pending`. Pixels overwritten by the newer frame are
+/// dropped, so every position is sent at most once.
+pub fn merge_pixels(pending: &mut Vec<Pixel>, newer: Vec<Pixel>) {
+    let overwritten = newer.iter().map(|p| (p.x, p.y)).collect::<HashSet<_>>();
+    pending.retain(|p| !overwritten.contains(&(p.x, p.y)));
+    pending.extend(newer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    #[test]
+    fn test_merge_pixels() {
+        let px = |x, y, v| Pixel::new(x, y, Color::new(v, v, v));
+        let mut pending = vec![px(0, 0, 1), px(1, 0, 1), px(2, 0, 1)];
+        merge_pixels(&mut pending, vec![px(1, 0, 2), px(3, 0, 2)]);
+        assert_eq!(pending, vec![px(0, 0, 1), px(2, 0, 1), px(1, 0, 2), px(3, 0, 2)]);
+    }
+
+    #[test]
+    fn test_sender_delivers_pixels() {
+        use std::io::Read;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let output = Output {
+            host: listener.local_addr().unwrap().to_string(),
+            protocol: Protocol::Plaintext,
+            canvas: 0,
+            x_offset: 100,
+            y_offset: 0,
+            region: None,
+            transform: OutputTransform::default(),
+        };
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        let sender = OutputSender::connect(output, pool, None).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        sender.send(&[Pixel::new(1, 2, Color::new(255, 0, 16))]).unwrap();
+        drop(sender);
+
+        let mut received = String::new();
+        server.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "PX 101 2 FF0010\n");
+    }
+
+    #[test]
+    fn test_lockstep_skips_frames_together() {
+        use std::io::Read;
+
+        let tile = |listener: &std::net::TcpListener, region| Output {
+            host: listener.local_addr().unwrap().to_string(),
+            protocol: Protocol::Plaintext,
+            canvas: 0,
+            x_offset: 0,
+            y_offset: 0,
+            region: Some(region),
+            transform: OutputTransform::default(),
+        };
+        let fast = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let slow = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let outputs = vec![
+            (tile(&fast, Region { x: 0, y: 0, width: 10, height: 10 }), None),
+            (tile(&slow, Region { x: 10, y: 0, width: 1000, height: 1000 }), None),
+        ];
+        let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        let senders = OutputSender::connect_lockstep(outputs, pool).unwrap();
+        let (fast, _) = fast.accept().unwrap();
+        let (slow, _) = slow.accept().unwrap();
+        let read = |mut server: std::net::TcpStream| {
+            thread::spawn(move || {
+                let mut received = String::new();
+                server.read_to_string(&mut received).unwrap();
+                received
+            })
+        };
+        let fast = read(fast);
+
+        // far more than the socket buffers hold, so the slow tile is stuck until its server reads
+        let c = Color::new(0, 0, 1);
+        let big = (0..1000).flat_map(|y| (10..1010).map(move |x| Pixel::new(x, y, c))).collect::<Vec<_>>();
+        let send = |pixels: &[Pixel]| {
+            senders.iter().for_each(|s| s.send(pixels).unwrap());
+            thread::sleep(std::time::Duration::from_millis(100));
+        };
+        send(&big);
+        send(&[Pixel::new(0, 0, Color::new(0, 0, 2))]);
+        send(&[Pixel::new(0, 0, Color::new(0, 0, 3))]);
+        let slow = read(slow);
+        drop(senders);
+
+        // the fast tile waited for the slow one and skipped the second frame along with it
+        assert_eq!(fast.join().unwrap(), "PX 0 0 000003\n");
+        assert_eq!(slow.join().unwrap().lines().count(), 1_000_000);
+    }
+
+    #[test]
+    fn test_transform() {
+        let rotated = OutputTransform { rotate: Some(Rotation::Rotate90), flip: None, scale: 1 };
+        // a 4x2 frame becomes 2x4, its top right corner the bottom right one
+        assert_eq!(rotated.place(0, 0, (4, 2)), (1, 0));
+        assert_eq!(rotated.place(3, 0, (4, 2)), (1, 3));
+        let mirrored = OutputTransform { flip: Some(Flip::Horizontal), ..rotated };
+        assert_eq!(mirrored.place(3, 0, (4, 2)), (0, 3));
+
+        let scaled = OutputTransform { scale: 2, ..OutputTransform::default() };
+        let pixels = [Pixel::new(1, 0, Color::new(0, 0, 0))];
+        let cmds = pixels_to_cmds(Protocol::Plaintext, 0, &pixels, 10, 0, &scaled, (2, 1));
+        assert_eq!(
+            String::from_utf8(cmds).unwrap(),
+            "PX 12 0 000000\nPX 13 0 000000\nPX 12 1 000000\nPX 13 1 000000\n"
+        );
+
+        let target: crate::Target =
+            toml::from_str("host = 'a:1'\nprotocol = 'plaintext'\nrotate = '270'\nscale = 3").unwrap();
+        assert_eq!(target.transform, OutputTransform { rotate: Some(Rotation::Rotate270), flip: None, scale: 3 });
+    }
+
+    #[test]
+    fn test_region_translation() {
+        let output = Output {
+            host: String::new(),
+            protocol: Protocol::default(),
+            canvas: 0,
+            x_offset: 0,
+            y_offset: 0,
+            region: Some(Region { x: 10, y: 5, width: 4, height: 4 }),
+            transform: OutputTransform::default(),
+        };
+        let c = Color::new(0, 0, 0);
+        let pixels = output.pixels(&[Pixel::new(9, 5, c), Pixel::new(10, 5, c), Pixel::new(13, 8, c)]);
+        assert_eq!(pixels, vec![Pixel::new(0, 0, c), Pixel::new(3, 3, c)]);
+    }
+}
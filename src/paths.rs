@@ -4,17 +4,14 @@ pub fn cache() -> PathBuf {
     dirs::cache_dir().unwrap()
         .join("bad-apple-flut")
 }
-pub fn cache_id() -> PathBuf {
-    cache().join("cache_id")
-}
-pub fn cache_frames() -> PathBuf {
-    cache().join("frames")
-}
-pub fn cache_metadata() -> PathBuf {
-    cache().join(format!("metadata"))
-}
-pub fn frame_file(idx: usize) -> PathBuf {
-    cache_frames().join(format!("frame{}.ppm", idx))
+/// Frame caches of additional inputs, see [`crate::FrameCache::input`]
+pub fn cache_inputs() -> PathBuf {
+    cache().join("inputs")
+}
+/// Name of a file holding compressed frames of this process, see [`crate::SpillFile`]. `id` tells
+/// apart the spill files of the videos of a playlist. The file is unlinked as soon as it is created.
+pub fn spill_file_name(id: usize) -> String {
+    format!("frames-{}-{}.spill", std::process::id(), id)
 }
 
 pub fn config_dir() -> PathBuf {
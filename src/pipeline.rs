@@ -0,0 +1,251 @@
+use std::fmt::Display;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{
+    frame::{Frame, FrameData},
+    Compositor, FrameMetrics, Quantizer, Result, TiledCompressor,
+};
+
+/// Time a pipeline stage spent on its frames
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StageTiming {
+    pub frames: usize,
+    /// Time spent working
+    pub busy: Duration,
+    /// Time spent waiting for the previous stage
+    pub starved: Duration,
+    /// Time spent waiting for the next stage to accept a frame
+    pub blocked: Duration,
+}
+
+impl StageTiming {
+    fn per_frame(&self, d: Duration) -> Duration {
+        d / self.frames.max(1) as u32
+    }
+}
+
+/// Shared handle for recording the [`StageTiming`] of a stage from its thread
+#[derive(Debug, Clone, Default)]
+pub struct StageClock(Arc<Mutex<StageTiming>>);
+
+impl StageClock {
+    /// Runs the work of one frame, counting its duration as busy time
+    pub fn busy<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let mut timing = self.0.lock().unwrap();
+        timing.busy += start.elapsed();
+        timing.frames += 1;
+        result
+    }
+    pub fn starved<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.0.lock().unwrap().starved += start.elapsed();
+        result
+    }
+    pub fn blocked<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.0.lock().unwrap().blocked += start.elapsed();
+        result
+    }
+
+    pub fn timing(&self) -> StageTiming {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Timings of all pipeline stages, relative to the time available per frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineStats {
+    pub frame_time: Duration,
+    pub stages: Vec<(&'static str, StageTiming)>,
+}
+
+impl PipelineStats {
+    /// The stage with the most busy time per frame, which limits the throughput of the pipeline
+    pub fn bottleneck(&self) -> Option<&'static str> {
+        self.stages
+            .iter()
+            .max_by_key(|(_, t)| t.per_frame(t.busy))
+            .map(|(name, _)| *name)
+    }
+}
+
+impl Display for PipelineStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+
+        writeln!(
+            f,
+            "{:<10} {:>10} {:>10} {:>10} {:>7}",
+            "stage", "busy", "starved", "blocked", "load"
+        )?;
+        for (name, t) in &self.stages {
+            let busy = t.per_frame(t.busy);
+            writeln!(
+                f,
+                "{:<10} {:>7.2} ms {:>7.2} ms {:>7.2} ms {:>6.0}%",
+                name,
+                ms(busy),
+                ms(t.per_frame(t.starved)),
+                ms(t.per_frame(t.blocked)),
+                100.0 * busy.as_secs_f64() / self.frame_time.as_secs_f64().max(f64::EPSILON),
+            )?;
+        }
+        write!(f, "bottleneck: {}", self.bottleneck().unwrap_or("-"))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+/// A frame that went through the decode and compress stages
+#[derive(Debug, Clone)]
+pub struct CompressedFrame {
+    /// 1-based frame index
+    pub idx: usize,
+    /// Number of times the video was played before this frame
+    pub pass: usize,
+    pub frame_data: FrameData,
+    /// Only computed during the first pass
+    pub metrics: Option<FrameMetrics>,
+}
+
+struct DecodedFrame {
+    idx: usize,
+    pass: usize,
+    frame: Frame,
+}
+
+/// Decodes and compresses frames just-in-time on dedicated threads. The stages are connected by
+/// bounded channels, so at most `lookahead` frames are buffered between two stages. The last
+/// stage (sending) runs on the thread that calls [`JitPipeline::recv`].
+pub struct JitPipeline {
+    frames: Receiver<Result<CompressedFrame>>,
+    decode: StageClock,
+    compress: StageClock,
+    send: StageClock,
+    _handles: [JoinHandle<()>; 2],
+}
+
+impl JitPipeline {
+    /// Loops over the `frame_count` frames in the cache until the pipeline is dropped, starting at
+    /// frame `start`
+    pub fn spawn(
+        frame_count: usize,
+        start: usize,
+        compositor: Arc<Compositor>,
+        lookahead: usize,
+        mut compressor: TiledCompressor,
+        mut quantizer: Option<Quantizer>,
+        metrics: bool,
+    ) -> Self {
+        let (decoded_tx, decoded_rx) = sync_channel::<Result<DecodedFrame>>(lookahead);
+        let (compressed_tx, compressed_rx) = sync_channel::<Result<CompressedFrame>>(lookahead);
+
+        let decode = StageClock::default();
+        let compress = StageClock::default();
+
+        let decode_handle = {
+            let clock = decode.clone();
+            thread::spawn(move || {
+                for pass in 0.. {
+                    let first = if pass == 0 { start } else { 1 };
+                    for idx in first..=frame_count {
+                        let frame = clock
+                            .busy(|| compositor.load_frame(idx))
+                            .map(|frame| DecodedFrame { idx, pass, frame });
+                        if clock.blocked(|| send_or_stop(&decoded_tx, frame)) {
+                            return;
+                        }
+                    }
+                }
+            })
+        };
+
+        let compress_handle = {
+            let clock = compress.clone();
+            thread::spawn(move || {
+                while let Ok(decoded) = clock.starved(|| decoded_rx.recv()) {
+                    let compressed = decoded.map(|DecodedFrame { idx, pass, frame }| {
+                        clock.busy(|| {
+                            let quantized = quantizer.as_mut().map(|q| q.process(&frame));
+                            let frame_data =
+                                compressor.compress_frame(quantized.as_ref().unwrap_or(&frame));
+
+                            let metrics = (metrics && pass == 0).then(|| compressor.metrics(idx, frame_data.len(), &frame));
+                            CompressedFrame { idx, pass, frame_data, metrics }
+                        })
+                    });
+                    if clock.blocked(|| send_or_stop(&compressed_tx, compressed)) {
+                        return;
+                    }
+                }
+            })
+        };
+
+        Self {
+            frames: compressed_rx,
+            decode,
+            compress,
+            send: StageClock::default(),
+            _handles: [decode_handle, compress_handle],
+        }
+    }
+
+    /// Waits for the next compressed frame
+    pub fn recv(&self) -> Result<CompressedFrame> {
+        self.send
+            .starved(|| self.frames.recv())
+            .expect("JIT pipeline stopped unexpectedly")
+    }
+
+    /// Clock of the send stage, which is driven by the caller. It only hands frames to the sender
+    /// threads of the outputs, so its busy time does not include the network.
+    #[inline] pub fn send_clock(&self) -> &StageClock { &self.send }
+
+    /// Timings of the stages. `network` is the time the slowest output spent encoding and writing
+    /// the frames on its sender thread; as it limits the send stage, it counts as its busy time
+    /// when it is longer than handing the frames over.
+    pub fn stats(&self, frame_time: Duration, network: Duration) -> PipelineStats {
+        let send = self.send.timing();
+        PipelineStats {
+            frame_time,
+            stages: vec![
+                ("decode", self.decode.timing()),
+                ("compress", self.compress.timing()),
+                ("send", StageTiming { busy: send.busy.max(network), ..send }),
+            ],
+        }
+    }
+}
+
+/// Forwards an item to the next stage. Returns true if the stage should stop, either because the
+/// item was an error or because the next stage is gone (the pipeline was dropped).
+fn send_or_stop<T>(tx: &SyncSender<Result<T>>, item: Result<T>) -> bool {
+    let failed = item.is_err();
+    tx.send(item).is_err() || failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bottleneck() {
+        let stage = |busy_ms| StageTiming {
+            frames: 10,
+            busy: Duration::from_millis(busy_ms),
+            ..Default::default()
+        };
+        let stats = PipelineStats {
+            frame_time: Duration::from_millis(33),
+            stages: vec![("decode", stage(50)), ("compress", stage(200)), ("send", stage(100))],
+        };
+        assert_eq!(stats.bottleneck(), Some("compress"));
+    }
+}
@@ -1,11 +1,46 @@
 use crate::Color;
 
+/// A single pixel update. Coordinates are stored as u16 (like the binary protocols and
+/// `FrameData::Full` do), which keeps a pixel at 8 bytes.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Pixel {
-    pub x: usize,
-    pub y: usize,
+    pub x: u16,
+    pub y: u16,
     pub color: Color,
 }
 
+impl Pixel {
+    #[inline]
+    pub fn new(x: usize, y: usize, color: Color) -> Self {
+        debug_assert!(x <= u16::MAX as usize && y <= u16::MAX as usize, "pixel out of range");
+        Self { x: x as u16, y: y as u16, color }
+    }
+    /// Pixel at index `i` of a row-major frame with the given width
+    #[inline]
+    pub fn at_index(i: usize, width: usize, color: Color) -> Self {
+        Self::new(i % width, i / width, color)
+    }
 
+    #[inline] pub fn x(&self) -> usize { self.x as usize }
+    #[inline] pub fn y(&self) -> usize { self.y as usize }
 
+    /// Index of the pixel in a row-major frame with the given width
+    #[inline]
+    pub fn index(&self, width: usize) -> usize {
+        self.y() * width + self.x()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixel_is_compact() {
+        assert_eq!(std::mem::size_of::<Pixel>(), 8);
+
+        let p = Pixel::at_index(1921 * 3 + 7, 1921, Color::new(1, 2, 3));
+        assert_eq!((p.x(), p.y()), (7, 3));
+        assert_eq!(p.index(1921), 1921 * 3 + 7);
+    }
+}
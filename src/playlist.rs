@@ -0,0 +1,275 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{frame::Frame, order::XorShift64, Args, Color, Error, Result};
+
+pub const DEFAULT_TRANSITION_DURATION: f64 = 1.0;
+
+/// How one playlist entry changes into the next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transition {
+    /// Switch to the next entry right away
+    #[default]
+    Cut,
+    /// Fade from the last frame of an entry into the next entry
+    Crossfade,
+    /// Reveal the next entry from left to right
+    Wipe,
+}
+
+impl Transition {
+    /// Blends `from` into `to`. `progress` goes from 0 (only `from`) to 1 (only `to`).
+    pub fn apply(&self, from: &Frame, to: &mut Frame, progress: f32) {
+        debug_assert_eq!((from.width(), from.height()), (to.width(), to.height()));
+        let progress = progress.clamp(0.0, 1.0);
+        match self {
+            Transition::Cut => {}
+            Transition::Crossfade => {
+                let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * progress).round() as u8;
+                for (t, f) in to.data_mut().iter_mut().zip(from.data()) {
+                    *t = Color::new(mix(f.r, t.r), mix(f.g, t.g), mix(f.b, t.b));
+                }
+            }
+            Transition::Wipe => {
+                let width = to.width();
+                let edge = (width as f32 * progress).round() as usize;
+                for (t, f) in to.data_mut().chunks_mut(width.max(1)).zip(from.data().chunks(width.max(1))) {
+                    t[edge..].copy_from_slice(&f[edge..]);
+                }
+            }
+        }
+    }
+}
+
+/// Moves `frame`, shown at `from_offset`, into the coordinates of a `width`x`height` frame shown
+/// at `to_offset`. Parts not covered by `frame` are black.
+pub fn reframe(
+    frame: &Frame,
+    from_offset: (usize, usize),
+    to_offset: (usize, usize),
+    width: usize,
+    height: usize,
+) -> Frame {
+    let mut data = vec![Color::new(0, 0, 0); width * height];
+    for (y, row) in data.chunks_mut(width.max(1)).enumerate() {
+        let Some(src_y) = (y + to_offset.1).checked_sub(from_offset.1) else { continue };
+        if src_y >= frame.height() {
+            continue;
+        }
+        for (x, c) in row.iter_mut().enumerate() {
+            let Some(src_x) = (x + to_offset.0).checked_sub(from_offset.0) else { continue };
+            if src_x < frame.width() {
+                *c = frame.data()[src_y * frame.width() + src_x];
+            }
+        }
+    }
+    Frame::new(width, height, data)
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+/// A video in a playlist. Every setting left out is taken from the args.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub input: String,
+    pub fps: Option<f64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub x_offset: Option<usize>,
+    pub y_offset: Option<usize>,
+    pub subtitles: Option<String>,
+}
+
+impl PlaylistEntry {
+    pub fn new(input: String) -> Self {
+        Self {
+            input,
+            fps: None,
+            width: None,
+            height: None,
+            x_offset: None,
+            y_offset: None,
+            subtitles: None,
+        }
+    }
+
+    /// `args` with the overrides of this entry
+    pub fn args(&self, args: &Args) -> Args {
+        let mut args = args.clone();
+        args.input = vec![self.input.clone()];
+        args.fps = self.fps.or(args.fps);
+        args.width = self.width.or(args.width);
+        args.height = self.height.or(args.height);
+        args.x_offset = self.x_offset.unwrap_or(args.x_offset);
+        args.y_offset = self.y_offset.unwrap_or(args.y_offset);
+        args.subtitles = self.subtitles.clone().or(args.subtitles);
+        args
+    }
+}
+
+/// Videos played back to back. Settings left out are taken from the args.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playlist {
+    pub shuffle: Option<bool>,
+    /// Start over after the last entry
+    #[serde(default = "default_repeat")]
+    pub repeat: bool,
+    pub transition: Option<Transition>,
+    /// Length of a transition (in s)
+    pub transition_duration: Option<f64>,
+    #[serde(default)]
+    pub entries: Vec<PlaylistEntry>,
+}
+
+fn default_repeat() -> bool {
+    true
+}
+
+impl Playlist {
+    /// Loads the playlist given with `--playlist`, or builds one from the `-i` inputs
+    pub fn from_args(args: &Args) -> Result<Self> {
+        let mut playlist = match &args.playlist {
+            Some(path) => {
+                if !args.input.is_empty() {
+                    return Err(Error::InvalidArgs(
+                        "--playlist cannot be combined with --input".to_string(),
+                    ));
+                }
+                let raw = std::fs::read_to_string(path)?;
+                toml::from_str::<Playlist>(&raw)
+                    .map_err(|e| Error::FileParseError(format!("{}: {}", path, e)))?
+            }
+            None => Playlist {
+                shuffle: None,
+                repeat: true,
+                transition: None,
+                transition_duration: None,
+                entries: args.input.iter().cloned().map(PlaylistEntry::new).collect(),
+            },
+        };
+        playlist.shuffle = Some(playlist.shuffle.unwrap_or(args.shuffle));
+        playlist.transition = Some(playlist.transition.unwrap_or(args.transition));
+        playlist.transition_duration = Some(
+            playlist.transition_duration
+                .or(args.transition_duration)
+                .unwrap_or(DEFAULT_TRANSITION_DURATION),
+        );
+
+        if playlist.entries.is_empty() {
+            return Err(Error::InvalidArgs("No input file specified".to_string()));
+        }
+        if playlist.transition_duration.unwrap() < 0.0 {
+            return Err(Error::InvalidArgs("transition_duration must not be negative".to_string()));
+        }
+        Ok(playlist)
+    }
+
+    /// Indices of the entries in play order
+    pub fn order(&self) -> PlaylistOrder {
+        let seed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        PlaylistOrder {
+            len: self.entries.len(),
+            shuffle: self.shuffle.unwrap_or(false),
+            repeat: self.repeat,
+            rng: XorShift64::new(seed),
+            round: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+/// Iterator over the entry indices of a [`Playlist`], reshuffled every round
+pub struct PlaylistOrder {
+    len: usize,
+    shuffle: bool,
+    repeat: bool,
+    rng: XorShift64,
+    round: Vec<usize>,
+    pos: usize,
+}
+
+impl Iterator for PlaylistOrder {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.pos == self.round.len() {
+            if !self.round.is_empty() && !self.repeat {
+                return None;
+            }
+            let last = self.round.last().copied();
+            self.round = (0..self.len).collect();
+            if self.shuffle {
+                self.rng.shuffle(&mut self.round);
+                // don't play the same entry twice in a row across rounds
+                if self.len > 1 && self.round.first().copied() == last {
+                    self.round.swap(0, self.len - 1);
+                }
+            }
+            self.pos = 0;
+        }
+        let idx = self.round.get(self.pos).copied();
+        self.pos += 1;
+        idx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(len: usize, shuffle: bool, repeat: bool) -> Playlist {
+        Playlist {
+            shuffle: Some(shuffle),
+            repeat,
+            transition: None,
+            transition_duration: None,
+            entries: (0..len).map(|i| PlaylistEntry::new(i.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let order = playlist(3, false, false).order().collect::<Vec<_>>();
+        assert_eq!(order, vec![0, 1, 2]);
+
+        let order = playlist(5, true, true).order().take(50).collect::<Vec<_>>();
+        for round in order.chunks(5) {
+            let mut sorted = round.to_vec();
+            sorted.sort();
+            assert_eq!(sorted, vec![0, 1, 2, 3, 4]);
+        }
+        assert!(order.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn test_transitions() {
+        let black = Frame::new(4, 1, vec![Color::new(0, 0, 0); 4]);
+        let white = Frame::new(4, 1, vec![Color::new(200, 200, 200); 4]);
+
+        let mut to = white.clone();
+        Transition::Crossfade.apply(&black, &mut to, 0.25);
+        assert!(to.data().iter().all(|c| *c == Color::new(50, 50, 50)));
+
+        let mut to = white.clone();
+        Transition::Wipe.apply(&black, &mut to, 0.5);
+        assert_eq!(&to.data()[..2], &white.data()[..2]);
+        assert_eq!(&to.data()[2..], &black.data()[2..]);
+    }
+
+    #[test]
+    fn test_reframe() {
+        let frame = Frame::debug(4, 4);
+        // (x, y) in the new frame shows (x + 1, y - 1) of the old one
+        let moved = reframe(&frame, (10, 10), (11, 9), 4, 4);
+        let black = Color::new(0, 0, 0);
+        assert!(moved.data()[..4].iter().all(|c| *c == black));
+        assert_eq!(moved.data()[4], frame.data()[1]);
+        assert_eq!(moved.data()[7], black);
+        assert_eq!(moved.data()[14], frame.data()[11]);
+    }
+}
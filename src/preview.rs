@@ -0,0 +1,76 @@
+use std::io::Write;
+
+use crossterm::{
+    cursor, queue, style::{self, Print}, terminal::{self, ClearType},
+};
+
+use crate::{
+    frame::{Frame, FrameData}, Color, Result,
+};
+
+/// Plays frames into the terminal instead of sending them to a server (`--dry-run`). The canvas
+/// is rebuilt from the compressed frames, so it shows what the wall would display.
+pub struct Preview {
+    canvas: Option<Frame>,
+}
+
+impl Preview {
+    pub fn new() -> Result<Self> {
+        crossterm::execute!(std::io::stdout(), terminal::Clear(ClearType::All))?;
+        Ok(Self { canvas: None })
+    }
+
+    /// Applies a compressed frame to the canvas and draws it, fit to the terminal
+    pub fn show(&mut self, frame_data: &FrameData) -> Result<()> {
+        let canvas = match &mut self.canvas {
+            Some(canvas) => {
+                canvas.apply_frame_data_mut(frame_data);
+                canvas
+            }
+            None => self.canvas.insert(Frame::from(frame_data.clone())),
+        };
+        let (cols, rows) = terminal::size()?;
+
+        let mut out = std::io::stdout().lock();
+        // keep the last line free, so the cursor doesn't scroll the screen
+        draw_half_blocks(&mut out, canvas, 0, cols, rows.saturating_sub(1))?;
+        queue!(out, terminal::Clear(ClearType::FromCursorDown))?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Draws `canvas` from row `top` on, downscaled to fit `cols` x `rows` cells. Every cell shows
+/// two pixels on top of each other, using a half block with different fore- and background colors.
+pub fn draw_half_blocks(out: &mut impl Write, canvas: &Frame, top: u16, cols: u16, rows: u16) -> Result<()> {
+    if canvas.width() == 0 || canvas.height() == 0 || cols == 0 || rows == 0 {
+        return Ok(());
+    }
+    let scale = (canvas.width() as f64 / cols as f64)
+        .max(canvas.height() as f64 / (rows as f64 * 2.0))
+        .max(1.0);
+    let width = ((canvas.width() as f64 / scale) as usize).max(1);
+    let height = ((canvas.height() as f64 / scale / 2.0) as usize).max(1);
+    let sample = |x: usize, y: usize| {
+        let sx = ((x as f64 * scale) as usize).min(canvas.width() - 1);
+        let sy = ((y as f64 * scale) as usize).min(canvas.height() - 1);
+        canvas.data()[sy * canvas.width() + sx]
+    };
+    let rgb = |Color { r, g, b }| style::Color::Rgb { r, g, b };
+
+    for row in 0..height {
+        queue!(out, cursor::MoveTo(0, top + row as u16))?;
+        // only change colors when they differ from the previous cell
+        let mut colors = None;
+        for x in 0..width {
+            let (fg, bg) = (sample(x, row * 2), sample(x, row * 2 + 1));
+            if colors != Some((fg, bg)) {
+                queue!(out, style::SetForegroundColor(rgb(fg)), style::SetBackgroundColor(rgb(bg)))?;
+                colors = Some((fg, bg));
+            }
+            queue!(out, Print('▀'))?;
+        }
+        queue!(out, style::ResetColor, terminal::Clear(ClearType::UntilNewLine))?;
+    }
+    Ok(())
+}
@@ -3,29 +3,50 @@ use std::io::Write as _;
 use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
-use crate::Pixel;
+use crate::{OutputTransform, Pixel};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, ValueEnum, Hash)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum, Hash)]
 #[serde(rename_all = "kebab-case")]
 pub enum Protocol {
+    #[default]
     Plaintext,
     BinFlutties,
     BinFlurry,
 }
 
+/// Encodes `pixels` of a `size` frame, placed with `transform` (see [`OutputTransform::place`])
 #[inline]
-pub fn pixels_to_cmds(protocol: Protocol, canvas: u8, pixels: &[Pixel], offset_x: usize, offset_y: usize) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::with_capacity(pixels.len() * 8);
-    for pixel in pixels {        
-        let x = (pixel.x + offset_x) as u16; 
-        let y = (pixel.y + offset_y) as u16;
-
-        protocol.encode(&mut result, canvas, pixel.color.r, pixel.color.g, pixel.color.b, x, y);
+pub fn pixels_to_cmds(
+    protocol: Protocol,
+    canvas: u8,
+    pixels: &[Pixel],
+    offset_x: usize,
+    offset_y: usize,
+    transform: &OutputTransform,
+    size: (usize, usize),
+) -> Vec<u8> {
+    let scale = transform.scale.max(1);
+    let mut result: Vec<u8> = Vec::with_capacity(pixels.len() * 8 * scale * scale);
+    let identity = transform.is_identity();
+    for pixel in pixels {
+        let (x, y) = match identity {
+            true => (pixel.x(), pixel.y()),
+            false => transform.place(pixel.x(), pixel.y(), size),
+        };
+        // scaled up pixels are sent as blocks
+        for dy in 0..scale {
+            for dx in 0..scale {
+                let x = (x + dx + offset_x) as u16;
+                let y = (y + dy + offset_y) as u16;
+                protocol.encode(&mut result, canvas, pixel.color.r, pixel.color.g, pixel.color.b, x, y);
+            }
+        }
     }
     result
 }
 
 impl Protocol {
+    #[allow(clippy::too_many_arguments)]
     pub fn encode(&self, buf: &mut Vec<u8>, canvas: u8, r: u8, g: u8, b: u8, x: u16, y: u16) {
         match self {
             Protocol::Plaintext => {
@@ -51,9 +72,3 @@ impl Protocol {
         }
     }
 }
-
-impl Default for Protocol {
-    fn default() -> Self {
-        Protocol::Plaintext
-    }
-}
\ No newline at end of file
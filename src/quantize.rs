@@ -0,0 +1,293 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{frame::Frame, Color, Error, Result};
+
+/// Bits per channel of the nearest-palette-color lookup table
+const LUT_BITS: u32 = 5;
+const LUT_LEVELS: usize = 1 << LUT_BITS;
+
+/// A pixel keeps its previous palette color unless the best match is closer by more than this
+/// (euclidean RGB distance). Stops dither noise from showing up as changed pixels every frame.
+const STABILITY_MARGIN: f32 = 24.0;
+
+// https://en.wikipedia.org/wiki/Ordered_dithering
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [ 0, 32,  8, 40,  2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44,  4, 36, 14, 46,  6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [ 3, 35, 11, 43,  1, 33,  9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47,  7, 39, 13, 45,  5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dither {
+    /// Map every pixel to the nearest palette color
+    #[default]
+    None,
+    /// Error diffusion
+    FloydSteinberg,
+    /// Ordered dithering with an 8x8 Bayer matrix
+    Bayer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Palette {
+    /// Black and white
+    Mono,
+    /// The corners of the RGB cube
+    EightColor,
+    /// 6x6x6 web-safe color cube
+    WebSafe,
+    Custom(Vec<Color>),
+}
+
+impl Palette {
+    pub fn colors(&self) -> Vec<Color> {
+        match self {
+            Self::Mono => vec![Color::new(0, 0, 0), Color::new(255, 255, 255)],
+            Self::EightColor => (0..8)
+                .map(|i| Color::new(
+                    if i & 1 != 0 { 255 } else { 0 },
+                    if i & 2 != 0 { 255 } else { 0 },
+                    if i & 4 != 0 { 255 } else { 0 },
+                ))
+                .collect(),
+            Self::WebSafe => (0..216)
+                .map(|i| Color::new((i / 36) as u8 * 51, (i / 6 % 6) as u8 * 51, (i % 6) as u8 * 51))
+                .collect(),
+            Self::Custom(colors) => colors.clone(),
+        }
+    }
+
+    /// Reads a palette file with one hex color (`rrggbb` or `#rrggbb`) per line
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
+        let colors = raw
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                Color::from_hex(line)
+                    .ok_or_else(|| Error::FileParseError(format!("invalid palette color '{}'", line)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if colors.is_empty() {
+            return Err(Error::FileParseError("palette file contains no colors".to_string()));
+        }
+        if colors.len() > u16::MAX as usize {
+            return Err(Error::FileParseError("palette file contains too many colors".to_string()));
+        }
+        Ok(Self::Custom(colors))
+    }
+}
+
+impl TryFrom<String> for Palette {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "1bit" | "mono" => Ok(Self::Mono),
+            "8color" => Ok(Self::EightColor),
+            "web-safe" => Ok(Self::WebSafe),
+            _ => Self::load(&value).map_err(|e| Error::InvalidArgs(
+                format!("Invalid palette '{}': {}", value, e)
+            )),
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+/// Reduces frames to a fixed palette before compression
+#[derive(Debug, Clone)]
+pub struct Quantizer {
+    palette: Arc<[Color]>,
+    /// Nearest palette index for every color, at `LUT_BITS` bits per channel
+    lut: Arc<[u16]>,
+    dither: Dither,
+    last_frame: Option<Frame>,
+}
+
+impl Quantizer {
+    pub fn new(palette: &Palette, dither: Dither) -> Self {
+        let colors: Arc<[Color]> = palette.colors().into();
+
+        let lut = (0..LUT_LEVELS.pow(3))
+            .into_par_iter()
+            .map(|i| {
+                let level = |c: usize| ((c * 255 + (LUT_LEVELS - 1) / 2) / (LUT_LEVELS - 1)) as f32;
+                let target = [
+                    level(i >> (2 * LUT_BITS)),
+                    level((i >> LUT_BITS) & (LUT_LEVELS - 1)),
+                    level(i & (LUT_LEVELS - 1)),
+                ];
+                nearest_exact(&colors, target) as u16
+            })
+            .collect();
+
+        Self { palette: colors, lut, dither, last_frame: None }
+    }
+
+    #[inline]
+    fn nearest(&self, target: [f32; 3]) -> usize {
+        let idx = |c: f32| (c.clamp(0.0, 255.0) as usize * (LUT_LEVELS - 1) + 127) / 255;
+        let i = (idx(target[0]) << (2 * LUT_BITS)) | (idx(target[1]) << LUT_BITS) | idx(target[2]);
+        self.lut[i] as usize
+    }
+
+    /// Picks the palette color for a pixel, preferring the color it had in the previous frame
+    #[inline]
+    fn choose(&self, target: [f32; 3], previous: Option<&Color>) -> Color {
+        let best = self.palette[self.nearest(target)];
+        match previous {
+            Some(prev) if distance(prev, target) <= distance(&best, target) + STABILITY_MARGIN => *prev,
+            _ => best,
+        }
+    }
+
+    pub fn process(&mut self, frame: &Frame) -> Frame {
+        let width = frame.width();
+        let last = self
+            .last_frame
+            .as_ref()
+            .filter(|lf| lf.width() == width && lf.height() == frame.height());
+
+        let mut data = frame.data().to_vec();
+
+        match self.dither {
+            Dither::None => {
+                data.par_iter_mut().enumerate().for_each(|(i, c)| {
+                    let prev = last.map(|lf| &lf.data()[i]);
+                    *c = self.choose(to_f32(c), prev);
+                });
+            }
+            Dither::Bayer => {
+                let n = self.palette.len() as f32;
+                let spread = (255.0 / (n.cbrt() - 1.0).max(f32::EPSILON)).min(255.0);
+
+                data.par_iter_mut().enumerate().for_each(|(i, c)| {
+                    let (x, y) = (i % width, i / width);
+                    let offset = ((BAYER_8X8[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5) * spread;
+                    let [r, g, b] = to_f32(c);
+                    let prev = last.map(|lf| &lf.data()[i]);
+                    *c = self.choose([r + offset, g + offset, b + offset], prev);
+                });
+            }
+            Dither::FloydSteinberg => {
+                // error diffused to the current and next row
+                let mut err = vec![[0.0f32; 3]; width + 2];
+                let mut next_err = vec![[0.0f32; 3]; width + 2];
+
+                for (y, row) in data.chunks_mut(width.max(1)).enumerate() {
+                    for (x, c) in row.iter_mut().enumerate() {
+                        let [r, g, b] = to_f32(c);
+                        let e = err[x + 1];
+                        let target = [r + e[0], g + e[1], b + e[2]];
+
+                        let prev = last.map(|lf| &lf.data()[y * width + x]);
+                        *c = self.choose(target, prev);
+
+                        // diffuse the error of the best match, even if the previous color was
+                        // kept, so kept pixels don't push their neighbours away from their
+                        // previous colors as well
+                        let out = to_f32(&self.palette[self.nearest(target)]);
+                        for ch in 0..3 {
+                            let q = target[ch] - out[ch];
+                            err[x + 2][ch] += q * 7.0 / 16.0;
+                            next_err[x][ch] += q * 3.0 / 16.0;
+                            next_err[x + 1][ch] += q * 5.0 / 16.0;
+                            next_err[x + 2][ch] += q * 1.0 / 16.0;
+                        }
+                    }
+                    std::mem::swap(&mut err, &mut next_err);
+                    next_err.fill([0.0; 3]);
+                }
+            }
+        }
+
+        let out = Frame::new(width, frame.height(), data);
+        self.last_frame = Some(out.clone());
+        out
+    }
+}
+
+#[inline]
+fn to_f32(c: &Color) -> [f32; 3] {
+    [c.r as f32, c.g as f32, c.b as f32]
+}
+
+#[inline]
+fn distance(c: &Color, target: [f32; 3]) -> f32 {
+    let [r, g, b] = to_f32(c);
+    ((r - target[0]).powi(2) + (g - target[1]).powi(2) + (b - target[2]).powi(2)).sqrt()
+}
+
+fn nearest_exact(palette: &[Color], target: [f32; 3]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance(a, target).total_cmp(&distance(b, target)))
+        .map(|(i, _)| i)
+        .expect("palette is empty")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(offset: u8) -> Frame {
+        let data = (0..64 * 16)
+            .map(|i| {
+                let v = ((i % 64) * 4) as u8;
+                Color::new(v.saturating_add(offset), v, 255 - v)
+            })
+            .collect::<Vec<_>>();
+        Frame::new(64, 16, data)
+    }
+
+    #[test]
+    fn test_palette_sizes() {
+        assert_eq!(Palette::Mono.colors().len(), 2);
+        assert_eq!(Palette::EightColor.colors().len(), 8);
+        assert_eq!(Palette::WebSafe.colors().len(), 216);
+    }
+
+    #[test]
+    fn test_output_uses_palette() {
+        for dither in [Dither::None, Dither::FloydSteinberg, Dither::Bayer] {
+            let palette = Palette::EightColor;
+            let out = Quantizer::new(&palette, dither).process(&gradient(0));
+            assert!(out.data().iter().all(|c| palette.colors().contains(c)), "{:?}", dither);
+        }
+    }
+
+    #[test]
+    fn test_temporal_stability() {
+        let changed = |a: &Frame, b: &Frame| a.data().iter().zip(b.data()).filter(|(a, b)| a != b).count();
+
+        for dither in [Dither::None, Dither::FloydSteinberg, Dither::Bayer] {
+            let mut q = Quantizer::new(&Palette::WebSafe, dither);
+            let a = q.process(&gradient(0));
+            // slight noise in the source
+            let b = q.process(&gradient(3));
+            let without_history = Quantizer::new(&Palette::WebSafe, dither).process(&gradient(3));
+
+            if dither == Dither::FloydSteinberg {
+                // error diffusion can't be fully stable, but should be close
+                assert!(changed(&a, &b) * 10 < changed(&a, &without_history));
+            } else {
+                assert_eq!(changed(&a, &b), 0, "{:?}", dither);
+            }
+        }
+    }
+}
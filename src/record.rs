@@ -0,0 +1,332 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Output, Protocol, Result};
+
+/// First bytes of a recording, the last one being the format version
+const MAGIC: &[u8; 6] = b"PFREC\x01";
+
+/// Record declaring a stream: id (u16), JSON length (u32), [`StreamInfo`] as JSON
+const STREAM_RECORD: u8 = 0;
+/// Record holding sent bytes: time since the start (u64, in µs), stream id (u16), length (u32), bytes
+const DATA_RECORD: u8 = 1;
+
+/// Server a recorded stream was sent to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamInfo {
+    pub host: String,
+    pub protocol: Protocol,
+}
+
+/// An entry of a recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Stream { id: u16, info: StreamInfo },
+    Data { time: Duration, id: u16, bytes: Vec<u8> },
+}
+
+struct RecorderState {
+    out: BufWriter<File>,
+    /// Outputs that have a stream, by stream id
+    outputs: Vec<Output>,
+}
+
+/// Writes the bytes sent to every output, with the time they were sent, to a recording
+/// (`--record`). Every output gets a stream of its own.
+pub struct Recorder {
+    start: Instant,
+    state: Mutex<RecorderState>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let mut out = BufWriter::new(File::create(path).map_err(|e| {
+            Error::Custom(format!("Failed to create recording {}: {}", path.display(), e))
+        })?);
+        out.write_all(MAGIC)?;
+        Ok(Self {
+            start: Instant::now(),
+            state: Mutex::new(RecorderState { out, outputs: Vec::new() }),
+        })
+    }
+
+    /// Stream for `output`, declared in the recording the first time the output is seen
+    pub fn stream(self: &Arc<Self>, output: &Output) -> Result<RecordedStream> {
+        let mut state = self.state.lock().unwrap();
+        let id = match state.outputs.iter().position(|o| o == output) {
+            Some(id) => id,
+            None => {
+                let info = StreamInfo { host: output.host.clone(), protocol: output.protocol };
+                let json = serde_json::to_vec(&info).expect("Failed to serialize stream info");
+                let id = state.outputs.len();
+                state.out.write_all(&[STREAM_RECORD])?;
+                state.out.write_all(&(id as u16).to_le_bytes())?;
+                state.out.write_all(&(json.len() as u32).to_le_bytes())?;
+                state.out.write_all(&json)?;
+                state.outputs.push(output.clone());
+                id
+            }
+        };
+        Ok(RecordedStream { recorder: Arc::clone(self), id: id as u16 })
+    }
+
+    fn write(&self, id: u16, msgs: &[Vec<u8>]) -> std::io::Result<()> {
+        let time = self.start.elapsed().as_micros() as u64;
+        let len = msgs.iter().map(Vec::len).sum::<usize>();
+
+        let mut state = self.state.lock().unwrap();
+        state.out.write_all(&[DATA_RECORD])?;
+        state.out.write_all(&time.to_le_bytes())?;
+        state.out.write_all(&id.to_le_bytes())?;
+        state.out.write_all(&(len as u32).to_le_bytes())?;
+        msgs.iter().try_for_each(|msg| state.out.write_all(msg))?;
+        // playback usually ends with Ctrl-C, which skips dropping the recorder
+        state.out.flush()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.state.lock().unwrap().out.flush();
+    }
+}
+
+/// Handle of a sender thread to its stream in a recording
+pub struct RecordedStream {
+    recorder: Arc<Recorder>,
+    id: u16,
+}
+
+impl RecordedStream {
+    /// Records the messages of one write to the server
+    pub fn record(&self, msgs: &[Vec<u8>]) -> std::io::Result<()> {
+        self.recorder.write(self.id, msgs)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+/// Reads the records of a recording in order
+pub struct RecordingReader {
+    input: BufReader<File>,
+}
+
+impl RecordingReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut input = BufReader::new(File::open(path).map_err(|e| {
+            Error::Custom(format!("Failed to open recording {}: {}", path.display(), e))
+        })?);
+        let mut magic = [0; MAGIC.len()];
+        if input.read_exact(&mut magic).is_err() || &magic != MAGIC {
+            return Err(Error::FileParseError(format!("{} is not a recording", path.display())));
+        }
+        Ok(Self { input })
+    }
+
+    /// The streams declared in the recording, by id
+    pub fn streams(path: &Path) -> Result<Vec<StreamInfo>> {
+        let mut reader = Self::open(path)?;
+        let mut streams = Vec::new();
+        while let Some(record) = reader.read(false)? {
+            if let Record::Stream { info, .. } = record {
+                streams.push(info);
+            }
+        }
+        Ok(streams)
+    }
+
+    /// Next record, if any. Without `data`, the bytes of data records are skipped. A record cut
+    /// off at the end (when recording was interrupted) ends the recording.
+    fn read(&mut self, data: bool) -> Result<Option<Record>> {
+        match self.read_record(data) {
+            Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+            result => result,
+        }
+    }
+
+    fn read_record(&mut self, data: bool) -> Result<Option<Record>> {
+        let mut kind = [0; 1];
+        if self.input.read(&mut kind)? == 0 {
+            return Ok(None);
+        }
+        let record = match kind[0] {
+            STREAM_RECORD => {
+                let id = u16::from_le_bytes(self.read_array()?);
+                let json = self.read_bytes()?;
+                let info = serde_json::from_slice(&json)
+                    .map_err(|e| Error::FileParseError(format!("corrupt stream record: {}", e)))?;
+                Record::Stream { id, info }
+            }
+            DATA_RECORD => {
+                let time = Duration::from_micros(u64::from_le_bytes(self.read_array()?));
+                let id = u16::from_le_bytes(self.read_array()?);
+                let bytes = match data {
+                    true => self.read_bytes()?,
+                    false => {
+                        let len = u32::from_le_bytes(self.read_array()?);
+                        self.input.seek(SeekFrom::Current(len as i64))?;
+                        Vec::new()
+                    }
+                };
+                Record::Data { time, id, bytes }
+            }
+            kind => {
+                return Err(Error::FileParseError(format!("unknown record type {}", kind)));
+            }
+        };
+        Ok(Some(record))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = u32::from_le_bytes(self.read_array()?);
+        let mut bytes = vec![0; len as usize];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read(true).transpose()
+    }
+}
+
+/// Totals of a replay
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayStats {
+    pub bytes: usize,
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for ReplayStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        write!(
+            f,
+            "{:.1} MiB in {:.2} s ({:.2} MiB/s)",
+            self.bytes as f64 / (1024.0 * 1024.0),
+            secs,
+            self.bytes as f64 / (1024.0 * 1024.0) / secs.max(f64::EPSILON)
+        )
+    }
+}
+
+/// Sends a recording again. Stream `i` goes to `hosts[i]`, or every stream to `hosts[0]` if there
+/// is only one host. With `fast`, the bytes are sent as fast as possible instead of with the
+/// original timing.
+pub fn replay(path: &Path, hosts: &[String], fast: bool) -> Result<ReplayStats> {
+    let mut connections = hosts
+        .iter()
+        .map(|host| {
+            TcpStream::connect(host)
+                .map(|stream| (host, stream))
+                .map_err(|e| Error::Custom(format!("Failed to connect to {}: {}", host, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let start = Instant::now();
+    let mut bytes_sent = 0;
+    for record in RecordingReader::open(path)? {
+        let Record::Data { time, id, bytes } = record? else { continue };
+        let (host, connection) = match connections.len() {
+            1 => &mut connections[0],
+            _ => connections.get_mut(id as usize).ok_or_else(|| {
+                Error::FileParseError(format!("data for undeclared stream {}", id))
+            })?,
+        };
+        if !fast {
+            std::thread::sleep(time.saturating_sub(start.elapsed()));
+        }
+        connection
+            .write_all(&bytes)
+            .map_err(|e| Error::Custom(format!("Unable to send to {}: {}", host, e)))?;
+        bytes_sent += bytes.len();
+    }
+    for (_, connection) in &mut connections {
+        connection.flush()?;
+    }
+
+    Ok(ReplayStats { bytes: bytes_sent, elapsed: start.elapsed() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OutputTransform;
+
+    fn output(host: &str) -> Output {
+        Output {
+            host: host.to_string(),
+            protocol: Protocol::Plaintext,
+            canvas: 0,
+            x_offset: 0,
+            y_offset: 0,
+            region: None,
+            transform: OutputTransform::default(),
+        }
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.pfrec");
+
+        let recorder = Arc::new(Recorder::create(&path).unwrap());
+        let a = recorder.stream(&output("a:1")).unwrap();
+        let b = recorder.stream(&output("b:1")).unwrap();
+        a.record(&[b"PX 0 0 ".to_vec(), b"FFFFFF\n".to_vec()]).unwrap();
+        b.record(&[b"PX 1 1 000000\n".to_vec()]).unwrap();
+        // the same output keeps its stream
+        assert_eq!(recorder.stream(&output("a:1")).unwrap().id, 0);
+        drop((a, b, recorder));
+
+        let streams = RecordingReader::streams(&path).unwrap();
+        assert_eq!(streams.iter().map(|s| s.host.as_str()).collect::<Vec<_>>(), ["a:1", "b:1"]);
+
+        let data = RecordingReader::open(&path)
+            .unwrap()
+            .filter_map(|r| match r.unwrap() {
+                Record::Data { id, bytes, .. } => Some((id, bytes)),
+                Record::Stream { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(data, vec![(0, b"PX 0 0 FFFFFF\n".to_vec()), (1, b"PX 1 1 000000\n".to_vec())]);
+    }
+
+    #[test]
+    fn test_replay_to_one_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.pfrec");
+        let recorder = Arc::new(Recorder::create(&path).unwrap());
+        recorder.stream(&output("a:1")).unwrap().record(&[b"PX 0 0 FFFFFF\n".to_vec()]).unwrap();
+        recorder.stream(&output("b:1")).unwrap().record(&[b"PX 1 1 000000\n".to_vec()]).unwrap();
+        drop(recorder);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let stats = replay(&path, &[host], true).unwrap();
+        assert_eq!(stats.bytes, 28);
+        assert_eq!(server.join().unwrap(), "PX 0 0 FFFFFF\nPX 1 1 000000\n");
+    }
+}
@@ -0,0 +1,366 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::{self, JoinHandle};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{frame::FrameData, paths, Color, Error, Pixel, Result};
+
+/// Number of frames the reader decodes ahead of playback
+pub const SPILL_PREFETCH_FRAMES: usize = 16;
+
+const TAG_EMPTY: u8 = 0;
+const TAG_FULL: u8 = 1;
+const TAG_DELTA: u8 = 2;
+
+/// Compression applied to every frame in a spill file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpillCompression {
+    None,
+    /// Fast, usually shrinks frames by another 2-4x
+    #[default]
+    Lz4,
+}
+
+/// Encodes a frame into a compact byte representation. Positions of delta pixels are delta-coded
+/// as varints, which is most compact for pixels in scanline order, but any order is preserved
+/// (see [`crate::PixelOrder::Compressor`]).
+pub fn encode_frame_data(frame_data: &FrameData) -> Vec<u8> {
+    match frame_data {
+        FrameData::Empty => vec![TAG_EMPTY],
+        FrameData::Full { width, height, data } => {
+            let mut out = Vec::with_capacity(5 + data.len() * 3);
+            out.push(TAG_FULL);
+            out.extend_from_slice(&width.to_le_bytes());
+            out.extend_from_slice(&height.to_le_bytes());
+            for c in data {
+                out.extend_from_slice(&[c.r, c.g, c.b]);
+            }
+            out
+        }
+        FrameData::Delta(pixels) => {
+            let mut out = Vec::with_capacity(5 + pixels.len() * 5);
+            out.push(TAG_DELTA);
+            write_varint(&mut out, pixels.len() as u64);
+
+            let (mut last_x, mut last_y) = (0, 0);
+            for p in pixels {
+                // rows are stored as the difference to the previous row, columns as the
+                // difference to the previous pixel in the same row (both zigzag-coded, as they
+                // may be negative)
+                let dy = p.y as i64 - last_y as i64;
+                write_varint(&mut out, zigzag(dy));
+                let x = if dy == 0 { p.x as i64 - last_x as i64 } else { p.x as i64 };
+                write_varint(&mut out, zigzag(x));
+                out.extend_from_slice(&[p.color.r, p.color.g, p.color.b]);
+                (last_x, last_y) = (p.x, p.y);
+            }
+            out
+        }
+    }
+}
+
+pub fn decode_frame_data(mut bytes: &[u8]) -> Result<FrameData> {
+    let invalid = || Error::FileParseError("corrupt spill frame".to_string());
+    let bytes = &mut bytes;
+
+    match take(bytes, 1).ok_or_else(invalid)?[0] {
+        TAG_EMPTY => Ok(FrameData::Empty),
+        TAG_FULL => {
+            let width = u16::from_le_bytes(take(bytes, 2).ok_or_else(invalid)?.try_into().unwrap());
+            let height = u16::from_le_bytes(take(bytes, 2).ok_or_else(invalid)?.try_into().unwrap());
+            let data = take(bytes, width as usize * height as usize * 3)
+                .ok_or_else(invalid)?
+                .chunks_exact(3)
+                .map(|c| Color::new(c[0], c[1], c[2]))
+                .collect();
+            Ok(FrameData::Full { width, height, data })
+        }
+        TAG_DELTA => {
+            let len = read_varint(bytes).ok_or_else(invalid)? as usize;
+
+            let mut pixels = Vec::with_capacity(len.min(bytes.len()));
+            let (mut x, mut y) = (0, 0);
+            for _ in 0..len {
+                let dy = unzigzag(read_varint(bytes).ok_or_else(invalid)?);
+                let dx = unzigzag(read_varint(bytes).ok_or_else(invalid)?);
+                (x, y) = if dy == 0 { (x + dx, y) } else { (dx, y + dy) };
+                if !(0..=u16::MAX as i64).contains(&x) || !(0..=u16::MAX as i64).contains(&y) {
+                    return Err(invalid());
+                }
+
+                let c = take(bytes, 3).ok_or_else(invalid)?;
+                pixels.push(Pixel::new(x as usize, y as usize, Color::new(c[0], c[1], c[2])));
+            }
+            Ok(FrameData::Delta(pixels))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if bytes.len() < n {
+        return None;
+    }
+    let (head, tail) = bytes.split_at(n);
+    *bytes = tail;
+    Some(head)
+}
+
+// https://en.wikipedia.org/wiki/Variable-length_quantity#Zigzag_encoding
+#[inline]
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[inline]
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+// https://en.wikipedia.org/wiki/LEB128
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for (i, b) in bytes.iter().enumerate().take(10) {
+        value |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+/// Writes compressed frames to a spill file in the cache directory
+pub struct SpillWriter {
+    file: SpillFile,
+    out: BufWriter<File>,
+}
+
+impl SpillWriter {
+    /// Creates a spill file in the cache directory
+    pub fn create(compression: SpillCompression) -> Result<Self> {
+        Self::create_in(&paths::cache(), compression)
+    }
+
+    /// Creates a spill file in `dir`
+    pub fn create_in(dir: &Path, compression: SpillCompression) -> Result<Self> {
+        paths::create_dir_if_not_exists(&dir.to_path_buf());
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = dir.join(paths::spill_file_name(NEXT_ID.fetch_add(1, Ordering::Relaxed)));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        // playback usually ends with Ctrl-C or `process::exit`, which skip dropping the spill
+        // file, so it is unlinked right away and only lives on through the open handle. Where
+        // open files can't be removed, it is removed on drop instead.
+        let path = std::fs::remove_file(&path).is_err().then_some(path);
+        let out = BufWriter::new(file.try_clone()?);
+
+        Ok(Self {
+            file: SpillFile { file: Arc::new(file), path, frame_count: 0, compression },
+            out,
+        })
+    }
+
+    pub fn write(&mut self, frame_data: &FrameData) -> Result<()> {
+        let encoded = encode_frame_data(frame_data);
+        let bytes = match self.file.compression {
+            SpillCompression::None => encoded,
+            SpillCompression::Lz4 => lz4_flex::compress_prepend_size(&encoded),
+        };
+
+        self.out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.out.write_all(&bytes)?;
+        self.file.frame_count += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<SpillFile> {
+        self.out.flush()?;
+        Ok(self.file)
+    }
+}
+
+/// A finished spill file. The file has no name on disk (see [`SpillWriter::create`]), so it is
+/// gone once this is dropped or the process ends.
+#[derive(Debug)]
+pub struct SpillFile {
+    file: Arc<File>,
+    /// Path of the file, if it could not be unlinked while open
+    path: Option<PathBuf>,
+    frame_count: usize,
+    compression: SpillCompression,
+}
+
+impl SpillFile {
+    #[inline] pub fn frame_count(&self) -> usize { self.frame_count }
+
+    /// Size of the file in bytes
+    pub fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Streams the frames in order on a background thread, starting over after the last frame
+    pub fn stream(&self) -> Result<SpillReader> {
+        let file = Arc::clone(&self.file);
+        let mut input = BufReader::new(SpillCursor { file: Arc::clone(&file), pos: 0 });
+        let compression = self.compression;
+        let frame_count = self.frame_count();
+        let (tx, rx) = sync_channel(SPILL_PREFETCH_FRAMES);
+
+        let handle = thread::spawn(move || loop {
+            for _ in 0..frame_count {
+                let frame = read_frame(&mut input, compression);
+                let failed = frame.is_err();
+                // the receiver is gone once the reader is dropped
+                if tx.send(frame).is_err() || failed {
+                    return;
+                }
+            }
+            if frame_count == 0 {
+                return;
+            }
+            input = BufReader::new(SpillCursor { file: Arc::clone(&file), pos: 0 });
+        });
+
+        Ok(SpillReader { frames: rx, _handle: handle })
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Reads a spill file from a position of its own, so several readers can share the file handle
+struct SpillCursor {
+    file: Arc<File>,
+    pos: u64,
+}
+
+impl Read for SpillCursor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        let n = std::os::unix::fs::FileExt::read_at(&*self.file, buf, self.pos)?;
+        #[cfg(windows)]
+        let n = std::os::windows::fs::FileExt::seek_read(&*self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+fn read_frame(input: &mut impl Read, compression: SpillCompression) -> Result<FrameData> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+    input.read_exact(&mut bytes)?;
+
+    match compression {
+        SpillCompression::None => decode_frame_data(&bytes),
+        SpillCompression::Lz4 => decode_frame_data(
+            &lz4_flex::decompress_size_prepended(&bytes)
+                .map_err(|e| Error::FileParseError(format!("corrupt spill frame: {}", e)))?,
+        ),
+    }
+}
+
+/// Prefetching reader over a [`SpillFile`], see [`SpillFile::stream`]
+pub struct SpillReader {
+    frames: Receiver<Result<FrameData>>,
+    _handle: JoinHandle<()>,
+}
+
+impl SpillReader {
+    /// Waits for the next frame
+    pub fn recv(&self) -> Result<FrameData> {
+        self.frames
+            .recv()
+            .map_err(|_| Error::Custom("Spill reader stopped unexpectedly".to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(frame_data: FrameData) -> Vec<Pixel> {
+        let mut pixels = frame_data.to_pixels();
+        pixels.sort_unstable_by_key(|p| (p.y, p.x));
+        pixels
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let delta = FrameData::Delta(vec![
+            Pixel { x: 300, y: 7, color: Color::new(1, 2, 3) },
+            Pixel { x: 5, y: 0, color: Color::new(4, 5, 6) },
+            Pixel { x: 2, y: 7, color: Color::new(7, 8, 9) },
+            Pixel { x: 6, y: 1000, color: Color::new(255, 0, 255) },
+        ]);
+        let full = crate::Frame::debug(5, 3).to_full_frame_data();
+
+        for frame_data in [delta, full, FrameData::Empty] {
+            let decoded = decode_frame_data(&encode_frame_data(&frame_data)).unwrap();
+            assert_eq!(pixels(decoded), pixels(frame_data));
+        }
+    }
+
+    #[test]
+    fn test_encode_keeps_delta_order() {
+        let delta = vec![
+            Pixel { x: 300, y: 7, color: Color::new(1, 2, 3) },
+            Pixel { x: 5, y: 0, color: Color::new(4, 5, 6) },
+            Pixel { x: 2, y: 0, color: Color::new(7, 8, 9) },
+        ];
+        let decoded = decode_frame_data(&encode_frame_data(&FrameData::Delta(delta.clone()))).unwrap();
+        assert_eq!(decoded.to_pixels(), delta);
+    }
+
+    #[test]
+    fn test_spill_file_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = SpillWriter::create_in(dir.path(), SpillCompression::Lz4).unwrap();
+        let frames = [crate::Frame::debug(3, 2).to_full_frame_data(), FrameData::Empty];
+        frames.iter().for_each(|f| writer.write(f).unwrap());
+        let spill = writer.finish().unwrap();
+
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // readers don't share a position
+        let (a, b) = (spill.stream().unwrap(), spill.stream().unwrap());
+        assert_eq!(pixels(a.recv().unwrap()), pixels(frames[0].clone()));
+        assert_eq!(pixels(b.recv().unwrap()), pixels(frames[0].clone()));
+        assert!(matches!(a.recv().unwrap(), FrameData::Empty));
+        // starts over after the last frame
+        assert_eq!(pixels(a.recv().unwrap()), pixels(frames[0].clone()));
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_input() {
+        let encoded = encode_frame_data(&crate::Frame::debug(5, 3).to_full_frame_data());
+        assert!(decode_frame_data(&encoded[..encoded.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use crate::{Error, Result};
+
+/// A subtitle shown from `start` to `end` (in s)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// Subtitles of a video, read from an SRT or ASS/SSA file. Formatting is dropped, the text is
+/// drawn with the built-in font.
+#[derive(Debug, Clone, Default)]
+pub struct Subtitles {
+    cues: Vec<Cue>,
+}
+
+impl Subtitles {
+    /// Loads `path`, as ASS/SSA if the extension says so and as SRT otherwise
+    pub fn load(path: &str) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .map_err(|e| Error::Custom(format!("Failed to read subtitles {}: {}", path, e)))?;
+        let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or_default();
+        let subtitles = match extension.to_lowercase().as_str() {
+            "ass" | "ssa" => Self::parse_ass(&raw),
+            _ => Self::parse_srt(&raw),
+        };
+        subtitles.map_err(|e| Error::FileParseError(format!("{}: {}", path, e)))
+    }
+
+    pub fn parse_srt(raw: &str) -> std::result::Result<Self, String> {
+        let raw = raw.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+        let mut cues = Vec::new();
+        for block in raw.split("\n\n").map(str::trim).filter(|b| !b.is_empty()) {
+            let mut lines = block.lines();
+            // the counter is optional in practice
+            let mut timing = lines.next().unwrap_or_default();
+            if !timing.contains("-->") {
+                timing = lines.next().unwrap_or_default();
+            }
+            let (start, end) = timing
+                .split_once("-->")
+                .ok_or_else(|| format!("missing timing in '{}'", block))?;
+            cues.push(Cue {
+                start: parse_time(start)?,
+                end: parse_time(end)?,
+                text: strip_tags(&lines.collect::<Vec<_>>().join("\n"), '<', '>'),
+            });
+        }
+        Ok(Self { cues })
+    }
+
+    pub fn parse_ass(raw: &str) -> std::result::Result<Self, String> {
+        let mut cues = Vec::new();
+        // Start, End and Text of the default format
+        let (mut start_idx, mut end_idx, mut text_idx) = (1, 2, 9);
+        let mut in_events = false;
+        for line in raw.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_events = line.eq_ignore_ascii_case("[events]");
+                continue;
+            }
+            if !in_events {
+                continue;
+            }
+            if let Some(format) = line.strip_prefix("Format:") {
+                let fields = format.split(',').map(str::trim).collect::<Vec<_>>();
+                let find = |name: &str| fields.iter().position(|f| f.eq_ignore_ascii_case(name));
+                if let (Some(s), Some(e), Some(t)) = (find("Start"), find("End"), find("Text")) {
+                    (start_idx, end_idx, text_idx) = (s, e, t);
+                }
+            } else if let Some(dialogue) = line.strip_prefix("Dialogue:") {
+                // the text is the last field and may contain commas
+                let fields = dialogue.splitn(text_idx + 1, ',').collect::<Vec<_>>();
+                let field = |i: usize| fields.get(i).copied().ok_or_else(|| format!("invalid dialogue '{}'", line));
+                let text = strip_tags(field(text_idx)?, '{', '}')
+                    .replace("\\N", "\n")
+                    .replace("\\n", "\n")
+                    .replace("\\h", " ");
+                cues.push(Cue { start: parse_time(field(start_idx)?)?, end: parse_time(field(end_idx)?)?, text });
+            }
+        }
+        Ok(Self { cues })
+    }
+
+    /// Texts of the cues shown at `secs`
+    pub fn at(&self, secs: f64) -> impl Iterator<Item = &str> {
+        self.cues
+            .iter()
+            .filter(move |c| (c.start..c.end).contains(&secs))
+            .map(|c| c.text.as_str())
+    }
+}
+
+/// Parses `hh:mm:ss,mmm` (SRT) or `h:mm:ss.cc` (ASS)
+fn parse_time(time: &str) -> std::result::Result<f64, String> {
+    let time = time.trim();
+    let parts = time.replace(',', ".");
+    let mut secs = 0.0;
+    for part in parts.split(':') {
+        let value = part.parse::<f64>().map_err(|_| format!("invalid time '{}'", time))?;
+        secs = secs * 60.0 + value;
+    }
+    Ok(secs)
+}
+
+/// `text` without the parts between `open` and `close` (formatting tags)
+fn strip_tags(text: &str, open: char, close: char) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            c if c == open => depth += 1,
+            c if c == close && depth > 0 => depth -= 1,
+            c if depth == 0 => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_srt() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello</i>\r\nworld\r\n\r\n2\r\n00:01:00,000 --> 00:01:01,000\r\nBye\r\n";
+        let subtitles = Subtitles::parse_srt(srt).unwrap();
+        assert_eq!(subtitles.at(0.5).count(), 0);
+        assert_eq!(subtitles.at(2.0).collect::<Vec<_>>(), ["Hello\nworld"]);
+        assert_eq!(subtitles.at(60.5).collect::<Vec<_>>(), ["Bye"]);
+    }
+
+    #[test]
+    fn test_parse_ass() {
+        let ass = "[Script Info]\nTitle: test\n\n[Events]\n\
+            Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+            Dialogue: 0,0:00:01.00,0:00:03.50,Default,,0,0,0,,{\\i1}Hello{\\i0}, world\\Nagain\n";
+        let subtitles = Subtitles::parse_ass(ass).unwrap();
+        assert_eq!(subtitles.at(3.0).collect::<Vec<_>>(), ["Hello, world\nagain"]);
+        assert_eq!(subtitles.at(3.5).count(), 0);
+    }
+}
@@ -0,0 +1,253 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{frame::Frame, Color, Subtitles};
+
+/// Glyphs of the built-in font for ASCII 32 to 126. Every glyph is 5 columns wide, with the top
+/// row of a column in the lowest bit and descenders in the highest.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x08, 0x07, 0x03, 0x00], [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00], [0x2A, 0x1C, 0x7F, 0x1C, 0x2A], [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x80, 0x70, 0x30, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x00, 0x60, 0x60, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02], [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x72, 0x49, 0x49, 0x49, 0x46], [0x21, 0x41, 0x49, 0x4D, 0x33], [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39], [0x3C, 0x4A, 0x49, 0x49, 0x31], [0x41, 0x21, 0x11, 0x09, 0x07],
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x46, 0x49, 0x49, 0x29, 0x1E], [0x00, 0x00, 0x14, 0x00, 0x00],
+    [0x00, 0x40, 0x34, 0x00, 0x00], [0x00, 0x08, 0x14, 0x22, 0x41], [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x59, 0x09, 0x06], [0x3E, 0x41, 0x5D, 0x59, 0x4E],
+    [0x7C, 0x12, 0x11, 0x12, 0x7C], [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x41, 0x3E], [0x7F, 0x49, 0x49, 0x49, 0x41], [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x41, 0x51, 0x73], [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x1C, 0x02, 0x7F], [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x26, 0x49, 0x49, 0x49, 0x32], [0x03, 0x01, 0x7F, 0x01, 0x03], [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03], [0x61, 0x59, 0x49, 0x4D, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x41],
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x41, 0x7F], [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40], [0x00, 0x03, 0x07, 0x08, 0x00], [0x20, 0x54, 0x54, 0x78, 0x40],
+    [0x7F, 0x28, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x28], [0x38, 0x44, 0x44, 0x28, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18], [0x00, 0x08, 0x7E, 0x09, 0x02], [0x18, 0xA4, 0xA4, 0x9C, 0x78],
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], [0x20, 0x40, 0x40, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00], [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x78, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], [0xFC, 0x18, 0x24, 0x24, 0x18],
+    [0x18, 0x24, 0x24, 0x18, 0xFC], [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x24],
+    [0x04, 0x04, 0x3F, 0x44, 0x24], [0x3C, 0x40, 0x40, 0x20, 0x7C], [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], [0x44, 0x28, 0x10, 0x28, 0x44], [0x4C, 0x90, 0x90, 0x90, 0x7C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], [0x00, 0x00, 0x77, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00], [0x02, 0x01, 0x02, 0x04, 0x02],
+];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 8;
+/// Width of a character including the space after it (in font pixels)
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+/// Height of a line including the space below it (in font pixels)
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+
+fn glyph(c: char) -> &'static [u8; 5] {
+    match c {
+        ' '..='~' => &FONT[c as usize - 32],
+        _ => &FONT['?' as usize - 32],
+    }
+}
+
+/// Size (in px) of `text` drawn at `scale`, without the spacing after the last character and line
+pub fn text_size(text: &str, scale: usize) -> (usize, usize) {
+    let lines = text.lines().count().max(1);
+    let chars = text.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+    (
+        (chars * ADVANCE).saturating_sub(1) * scale,
+        (lines * LINE_HEIGHT - 1) * scale,
+    )
+}
+
+/// Draws `text` with the built-in font, every font pixel as a `scale`x`scale` block, with its top
+/// left corner at (`x`, `y`). With a `background`, a box with a margin of one font pixel is filled
+/// behind the text first. Parts outside the frame are cut off.
+pub fn draw_text(
+    frame: &mut Frame,
+    text: &str,
+    (x, y): (usize, usize),
+    scale: usize,
+    color: Color,
+    background: Option<Color>,
+) {
+    let scale = scale.max(1);
+    let mut fill = |fx: usize, fy: usize, w: usize, h: usize, c: Color| {
+        let width = frame.width();
+        for row in fy..(fy + h).min(frame.height()) {
+            for px in fx..(fx + w).min(width) {
+                frame.data_mut()[row * width + px] = c;
+            }
+        }
+    };
+
+    if let Some(background) = background {
+        let (w, h) = text_size(text, scale);
+        fill(x.saturating_sub(scale), y.saturating_sub(scale), w + 2 * scale, h + 2 * scale, background);
+    }
+    for (line_idx, line) in text.lines().enumerate() {
+        let top = y + line_idx * LINE_HEIGHT * scale;
+        for (char_idx, c) in line.chars().enumerate() {
+            let left = x + char_idx * ADVANCE * scale;
+            for (col, bits) in glyph(c).iter().enumerate() {
+                for row in (0..GLYPH_HEIGHT).filter(|row| bits & (1 << row) != 0) {
+                    fill(left + col * scale, top + row * scale, scale, scale, color);
+                }
+            }
+        }
+    }
+}
+
+/// Splits `text` into lines of at most `max_chars` characters, breaking at spaces where possible
+pub fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let len = line.chars().count();
+            if len > 0 && len + 1 + word.chars().count() > max_chars {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+            // words longer than a line are split
+            while line.chars().count() > max_chars {
+                let rest = line.chars().skip(max_chars).collect();
+                line = line.chars().take(max_chars).collect();
+                lines.push(std::mem::replace(&mut line, rest));
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+/// Text drawn on top of the video, e.g. a title or a clock. The text can contain placeholders:
+/// `{input}` (file name of the video), `{time}` and `{duration}` (m:ss), `{frame}`,
+/// `{frame_count}` and `{clock}` (UTC, hh:mm:ss).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextOverlay {
+    pub text: String,
+    /// Position of the top left corner of the text in the video (in px)
+    #[serde(default)]
+    pub x: usize,
+    #[serde(default)]
+    pub y: usize,
+    /// Size of a font pixel (in px) [default: 1]
+    pub scale: Option<usize>,
+    /// [default: ffffff]
+    pub color: Option<Color>,
+    /// Color of a box behind the text [default: none]
+    pub background: Option<Color>,
+}
+
+/// Draws text overlays and subtitles onto the frames of a video
+pub struct TextRenderer {
+    pub overlays: Vec<TextOverlay>,
+    pub subtitles: Option<Subtitles>,
+    /// Size of a font pixel of the subtitles (in px) [default: about 1/240 of the frame height]
+    pub subtitle_scale: Option<usize>,
+    /// Video the frames belong to
+    pub input: String,
+    pub fps: f64,
+    pub frame_count: usize,
+}
+
+impl TextRenderer {
+    /// Draws the text shown on frame `idx` (1-based)
+    pub fn draw(&self, frame: &mut Frame, idx: usize) {
+        for overlay in &self.overlays {
+            draw_text(
+                frame,
+                &self.expand(&overlay.text, idx),
+                (overlay.x, overlay.y),
+                overlay.scale.unwrap_or(1),
+                overlay.color.unwrap_or(Color::new(255, 255, 255)),
+                overlay.background,
+            );
+        }
+
+        let Some(subtitles) = &self.subtitles else { return };
+        let scale = self.subtitle_scale.unwrap_or((frame.height() / 240).max(1)).max(1);
+        let max_chars = frame.width().saturating_sub(4 * scale) / (ADVANCE * scale);
+        let text = subtitles.at((idx - 1) as f64 / self.fps).collect::<Vec<_>>().join("\n");
+        if text.is_empty() {
+            return;
+        }
+        let lines = wrap_text(&text, max_chars);
+
+        // centered lines above the bottom edge
+        let line_height = LINE_HEIGHT * scale;
+        let bottom = frame.height().saturating_sub(frame.height() / 20 + line_height);
+        for (i, line) in lines.iter().rev().enumerate() {
+            let Some(y) = bottom.checked_sub(i * (line_height + scale)) else { break };
+            let x = frame.width().saturating_sub(text_size(line, scale).0) / 2;
+            draw_text(frame, line, (x, y), scale, Color::new(255, 255, 255), Some(Color::new(0, 0, 0)));
+        }
+    }
+
+    /// `text` with the placeholders filled in for frame `idx`
+    fn expand(&self, text: &str, idx: usize) -> String {
+        if !text.contains('{') {
+            return text.to_string();
+        }
+        let minutes = |frames: usize| {
+            let secs = (frames as f64 / self.fps) as u64;
+            format!("{}:{:02}", secs / 60, secs % 60)
+        };
+        let input = Path::new(&self.input)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.input.clone());
+        let clock = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() % 86400)
+            .unwrap_or(0);
+
+        text.replace("{input}", &input)
+            .replace("{time}", &minutes(idx - 1))
+            .replace("{duration}", &minutes(self.frame_count))
+            .replace("{frame}", &idx.to_string())
+            .replace("{frame_count}", &self.frame_count.to_string())
+            .replace("{clock}", &format!("{:02}:{:02}:{:02}", clock / 3600, clock / 60 % 60, clock % 60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw_text() {
+        let black = Color::new(0, 0, 0);
+        let white = Color::new(255, 255, 255);
+        let mut frame = Frame::new(8, 10, vec![black; 80]);
+        draw_text(&mut frame, "I", (1, 1), 1, white, None);
+
+        // 'I' is a vertical bar in the middle column, with serifs in rows 0 and 6
+        let lit = |x: usize, y: usize| frame.data()[y * 8 + x] == white;
+        assert!((1..8).all(|y| lit(3, y)));
+        assert!(lit(2, 1) && lit(4, 1) && lit(2, 7) && lit(4, 7));
+        assert!(!lit(2, 4) && !lit(3, 8) && !lit(0, 0));
+
+        let (w, h) = text_size("ab\nc", 2);
+        assert_eq!((w, h), (22, 34));
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        assert_eq!(wrap_text("the quick brown fox", 10), vec!["the quick", "brown fox"]);
+        assert_eq!(wrap_text("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(wrap_text("a\nb", 10), vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    frame::{Frame, FrameData},
+    Color, Error, FrameMetrics, Pixel, Result, VideoCompressor,
+};
+
+/// Rectangular region of the source frame (in px)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Region {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    #[inline]
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+    pub fn overlaps(&self, other: &Region) -> bool {
+        self.x < other.x + other.width
+            && other.x < self.x + self.width
+            && self.y < other.y + other.height
+            && other.y < self.y + self.height
+    }
+    /// Whether the region lies within a frame of the given size
+    pub fn fits(&self, width: usize, height: usize) -> bool {
+        self.x + self.width <= width && self.y + self.height <= height
+    }
+}
+
+/// Part of a video wall: a region of the source frame, played on one target
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tile {
+    pub target: String,
+    #[serde(flatten)]
+    pub region: Region,
+}
+
+/// One video split across several targets
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Wall {
+    pub tiles: Vec<Tile>,
+}
+
+impl Wall {
+    /// Checks that the tiles are non-empty, lie within the frame and don't overlap
+    pub fn validate(&self, width: usize, height: usize) -> Result<()> {
+        if self.tiles.is_empty() {
+            return Err(Error::InvalidConfig("wall has no tiles".to_string()));
+        }
+        for (i, tile) in self.tiles.iter().enumerate() {
+            let r = &tile.region;
+            if r.width == 0 || r.height == 0 {
+                return Err(Error::InvalidConfig(format!("tile {} ({}) is empty", i, tile.target)));
+            }
+            if !r.fits(width, height) {
+                return Err(Error::InvalidConfig(format!(
+                    "tile {} ({}) does not fit in the {}x{} video", i, tile.target, width, height
+                )));
+            }
+            if let Some(j) = self.tiles[..i].iter().position(|t| t.region.overlaps(r)) {
+                return Err(Error::InvalidConfig(format!(
+                    "tiles {} ({}) and {} ({}) overlap", j, self.tiles[j].target, i, tile.target
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone)]
+enum Tiles {
+    Whole(Box<VideoCompressor>),
+    Regions(Vec<(Region, VideoCompressor)>),
+}
+
+/// Compresses every region of a frame with its own compressor, so every tile of a wall gets its
+/// own compression budget. The output is a single [`FrameData`] in source frame coordinates.
+#[derive(Clone)]
+pub struct TiledCompressor {
+    tiles: Tiles,
+    /// Size of the last compressed frame
+    size: Option<(usize, usize)>,
+}
+
+impl TiledCompressor {
+    /// Compresses the whole frame with `compressor`
+    pub fn whole(compressor: VideoCompressor) -> Self {
+        Self { tiles: Tiles::Whole(Box::new(compressor)), size: None }
+    }
+    /// Compresses every region with a clone of `compressor`
+    pub fn regions(compressor: VideoCompressor, regions: impl IntoIterator<Item = Region>) -> Self {
+        let tiles = regions.into_iter().map(|r| (r, compressor.clone())).collect();
+        Self { tiles: Tiles::Regions(tiles), size: None }
+    }
+
+    pub fn compress_frame(&mut self, new_frame: &Frame) -> FrameData {
+        let tiles = match &mut self.tiles {
+            Tiles::Whole(compressor) => return compressor.compress_frame(new_frame),
+            Tiles::Regions(tiles) => tiles,
+        };
+        self.size = Some((new_frame.width(), new_frame.height()));
+
+        let outputs = tiles
+            .par_iter_mut()
+            .map(|(region, compressor)| compressor.compress_frame(&new_frame.crop(region)))
+            .collect::<Vec<_>>();
+
+        if outputs.iter().all(|o| matches!(o, FrameData::Full { .. })) {
+            // keyframe, e.g. the first frame
+            let mut canvas = blank(new_frame.width(), new_frame.height());
+            for ((region, _), output) in tiles.iter().zip(outputs) {
+                canvas.paste(&Frame::from(output), region.x, region.y);
+            }
+            return canvas.to_full_frame_data();
+        }
+
+        let pixels = tiles
+            .iter()
+            .zip(&outputs)
+            .flat_map(|((region, _), output)| {
+                output.pixels().map(|p| Pixel::new(p.x() + region.x, p.y() + region.y, p.color))
+            })
+            .collect::<Vec<_>>();
+
+        if pixels.is_empty() { FrameData::Empty } else { FrameData::Delta(pixels) }
+    }
+
+    /// Metrics of the last compressed frame, compared to `source`. On a wall, only the tiles are
+    /// compared, as the rest of the frame is never shown; every tile counts by its area.
+    pub fn metrics(&self, frame: usize, pixels: usize, source: &Frame) -> FrameMetrics {
+        let tiles = match &self.tiles {
+            Tiles::Whole(compressor) => {
+                let canvas = compressor.canvas().expect("Canvas missing after compression");
+                return FrameMetrics::compute(frame, pixels, source, &canvas);
+            }
+            Tiles::Regions(tiles) => tiles,
+        };
+
+        let area = tiles.iter().map(|(r, _)| r.width * r.height).sum::<usize>().max(1) as f64;
+        let (mse, ssim) = tiles
+            .iter()
+            .map(|(region, compressor)| {
+                let canvas = compressor.canvas().expect("Canvas missing after compression");
+                let m = FrameMetrics::compute(frame, pixels, &source.crop(region), &canvas);
+                let weight = (region.width * region.height) as f64 / area;
+                (m.mse * weight, m.ssim * weight)
+            })
+            .fold((0.0, 0.0), |(mse, ssim), (m, s)| (mse + m, ssim + s));
+        FrameMetrics { frame, pixels, mse, ssim }
+    }
+
+    /// Number of compressors, each with its own budget
+    pub fn tile_count(&self) -> usize {
+        match &self.tiles {
+            Tiles::Whole(_) => 1,
+            Tiles::Regions(tiles) => tiles.len(),
+        }
+    }
+
+    /// Reconstructed canvas of all tiles. Parts of the frame not covered by a tile are black, so
+    /// use [`Self::metrics`] to compare it to the source.
+    pub fn canvas(&self) -> Option<Cow<'_, Frame>> {
+        let tiles = match &self.tiles {
+            Tiles::Whole(compressor) => return compressor.canvas(),
+            Tiles::Regions(tiles) => tiles,
+        };
+        let (width, height) = self.size?;
+
+        let mut canvas = blank(width, height);
+        for (region, compressor) in tiles {
+            canvas.paste(compressor.canvas()?.as_ref(), region.x, region.y);
+        }
+        Some(Cow::Owned(canvas))
+    }
+}
+
+fn blank(width: usize, height: usize) -> Frame {
+    Frame::new(width, height, vec![Color::new(0, 0, 0); width * height])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args::CompressionLevelArg, CompressionAlgConfig, CompressorOptions};
+
+    fn tile(target: &str, x: usize, y: usize, width: usize, height: usize) -> Tile {
+        Tile { target: target.to_string(), region: Region { x, y, width, height } }
+    }
+
+    #[test]
+    fn test_validate() {
+        let wall = Wall { tiles: vec![tile("a", 0, 0, 8, 8), tile("b", 8, 0, 8, 8)] };
+        assert!(wall.validate(16, 8).is_ok());
+        assert!(wall.validate(15, 8).is_err());
+
+        let wall = Wall { tiles: vec![tile("a", 0, 0, 8, 8), tile("b", 7, 0, 8, 8)] };
+        assert!(wall.validate(16, 8).is_err());
+    }
+
+    #[test]
+    fn test_tiles_reconstruct_frame() {
+        let compressor = VideoCompressor::new(
+            CompressionAlgConfig::V2,
+            CompressionLevelArg::Number(0),
+            &CompressorOptions::default(),
+        ).unwrap();
+        let regions = [Region { x: 0, y: 0, width: 5, height: 4 }, Region { x: 5, y: 0, width: 7, height: 4 }];
+        let mut tiled = TiledCompressor::regions(compressor, regions);
+
+        let a = Frame::debug(12, 4);
+        let mut b = a.clone();
+        b.data_mut()[3] = Color::new(255, 0, 0);
+        b.data_mut()[12 * 3 + 10] = Color::new(0, 0, 255);
+
+        let keyframe = tiled.compress_frame(&a);
+        assert!(matches!(keyframe, FrameData::Full { .. }));
+        let delta = tiled.compress_frame(&b);
+        assert_eq!(delta.len(), 2);
+
+        let wall = Frame::from(keyframe).apply_frame_data(&delta);
+        assert_eq!(wall.data(), b.data());
+        assert_eq!(tiled.canvas().unwrap().data(), b.data());
+    }
+
+    #[test]
+    fn test_metrics_ignore_uncovered_area() {
+        let compressor = VideoCompressor::new(
+            CompressionAlgConfig::V2,
+            CompressionLevelArg::Number(0),
+            &CompressorOptions::default(),
+        ).unwrap();
+        // the right half of the frame is not on the wall
+        let mut tiled = TiledCompressor::regions(compressor, [Region { x: 0, y: 0, width: 6, height: 4 }]);
+
+        let frame = Frame::debug(12, 4);
+        let frame_data = tiled.compress_frame(&frame);
+        let metrics = tiled.metrics(1, frame_data.len(), &frame);
+        assert_eq!(metrics.mse, 0.0);
+        assert!((metrics.ssim - 1.0).abs() < 1e-9);
+        assert!(FrameMetrics::compute(1, 0, &frame, &tiled.canvas().unwrap()).mse > 0.0);
+    }
+}
@@ -0,0 +1,291 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use colored::Colorize;
+use crossterm::{
+    cursor, event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue, style::Print, terminal::{self, ClearType},
+};
+
+use crate::{
+    draw_half_blocks, frame::{Frame, FrameData}, CompressionLevelArg, ControlCommand, PlaybackStatus,
+    Result,
+};
+
+/// Minimum time between two redraws
+const DRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// Length of the window the rates are measured over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Seconds skipped by the seek keys
+const SEEK_STEP: f64 = 5.0;
+/// Lines above the preview
+const HEADER_LINES: u16 = 5;
+
+/// What a key press asks for
+#[derive(Debug, Clone, PartialEq)]
+pub enum TuiInput {
+    Command(ControlCommand),
+    Quit,
+}
+
+/// Rates measured over the last [`RATE_WINDOW`]
+#[derive(Default)]
+struct Rates {
+    fps: f64,
+    pixels: f64,
+    bytes: f64,
+}
+
+/// Full-screen terminal UI shown during playback: stats, a downscaled preview of what the canvas
+/// should show and keybindings for the control commands. The terminal is restored on drop.
+pub struct Tui {
+    /// Reconstructed canvas, from the frames sent so far
+    canvas: Option<Frame>,
+    window_start: Instant,
+    window_frames: usize,
+    window_pixels: usize,
+    /// Bytes sent when the window started
+    window_bytes: usize,
+    rates: Rates,
+    /// Pixels sent and pixels of the whole frames since the last keyframe, for the compression ratio
+    sent_pixels: usize,
+    frame_pixels: usize,
+    /// Last notification or error, shown below the stats
+    message: Option<String>,
+    last_draw: Option<Instant>,
+    active: bool,
+}
+
+impl Tui {
+    /// Switches the terminal to the alternate screen and raw mode
+    pub fn start() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(std::io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self {
+            canvas: None,
+            window_start: Instant::now(),
+            window_frames: 0,
+            window_pixels: 0,
+            window_bytes: 0,
+            rates: Rates::default(),
+            sent_pixels: 0,
+            frame_pixels: 0,
+            message: None,
+            last_draw: None,
+            active: true,
+        })
+    }
+
+    /// Restores the terminal. Called on drop, but has to be called explicitly before exiting the
+    /// process.
+    pub fn restore(&mut self) {
+        if std::mem::take(&mut self.active) {
+            let _ = crossterm::execute!(std::io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+
+    pub fn set_message(&mut self, message: String) {
+        self.message = Some(message);
+    }
+
+    /// Records a frame that was sent, `bytes_sent` being the bytes sent to all outputs so far
+    pub fn record(&mut self, frame_data: &FrameData, bytes_sent: usize) {
+        match (&mut self.canvas, frame_data) {
+            (_, FrameData::Full { .. }) => {
+                self.canvas = Some(Frame::from(frame_data.clone()));
+                self.sent_pixels = 0;
+                self.frame_pixels = 0;
+            }
+            (Some(canvas), FrameData::Delta(pixels)) => {
+                // stale deltas can arrive for a canvas of another size, e.g. after switching videos
+                for p in pixels {
+                    let (x, y) = (p.x as usize, p.y as usize);
+                    if x < canvas.width() && y < canvas.height() {
+                        let width = canvas.width();
+                        canvas.data_mut()[y * width + x] = p.color;
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(canvas) = &self.canvas {
+            self.frame_pixels += canvas.width() * canvas.height();
+        }
+        self.sent_pixels += frame_data.len();
+        self.window_frames += 1;
+        self.window_pixels += frame_data.len();
+
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            let secs = elapsed.as_secs_f64();
+            self.rates = Rates {
+                fps: self.window_frames as f64 / secs,
+                pixels: self.window_pixels as f64 / secs,
+                // connections are reset when outputs change
+                bytes: bytes_sent.saturating_sub(self.window_bytes) as f64 / secs,
+            };
+            self.window_start = Instant::now();
+            self.window_frames = 0;
+            self.window_pixels = 0;
+            self.window_bytes = bytes_sent;
+        }
+    }
+
+    /// Redraws the screen, at most every [`DRAW_INTERVAL`] unless `force`d. `lag` is how far
+    /// playback is behind (in ms).
+    pub fn draw(&mut self, status: &PlaybackStatus, lag: u64, force: bool) -> Result<()> {
+        if !force && self.last_draw.is_some_and(|t| t.elapsed() < DRAW_INTERVAL) {
+            return Ok(());
+        }
+        self.last_draw = Some(Instant::now());
+
+        let (cols, rows) = terminal::size()?;
+        let ratio = match self.frame_pixels {
+            0 => 0.0,
+            n => self.sent_pixels as f64 / n as f64 * 100.0,
+        };
+        let state = match status.paused {
+            true => "paused".yellow(),
+            false => "playing".green(),
+        };
+        let time = |frame: usize| format_time(frame as f64 / status.fps.max(f64::EPSILON));
+        let header = [
+            format!("{} {} {}", "::".blue(), state, status.input),
+            format!(
+                "frame {} / {}   {} / {}",
+                status.frame, status.frame_count, time(status.frame), time(status.frame_count)
+            ),
+            format!("{:.1} / {:.2} fps   lag {} ms", self.rates.fps, status.fps, lag),
+            format!(
+                "{}/s   {}/s   {:.1}% of full frames   level {}",
+                format_count(self.rates.pixels, "px"),
+                format_count(self.rates.bytes, "B"),
+                ratio,
+                status.compression_level
+            ),
+            self.message.clone().unwrap_or_default(),
+        ];
+
+        let mut out = std::io::stdout().lock();
+        for (row, line) in header.iter().enumerate() {
+            queue!(out, cursor::MoveTo(0, row as u16), terminal::Clear(ClearType::CurrentLine), Print(line))?;
+        }
+        if let Some(canvas) = &self.canvas {
+            let height = rows.saturating_sub(HEADER_LINES + 1);
+            draw_half_blocks(&mut out, canvas, HEADER_LINES, cols, height)?;
+        }
+        queue!(
+            out,
+            terminal::Clear(ClearType::FromCursorDown),
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            Print("space pause   ←/→ seek   ↑/↓ budget   q quit".dimmed()),
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for a key press
+    pub fn poll_input(&self, status: &PlaybackStatus, timeout: Duration) -> Result<Option<TuiInput>> {
+        if !event::poll(timeout)? {
+            return Ok(None);
+        }
+        match event::read()? {
+            Event::Key(key) => Ok(key_input(key, status)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// Maps a key press to a command, given the current state of playback
+pub fn key_input(key: KeyEvent, status: &PlaybackStatus) -> Option<TuiInput> {
+    if key.kind == KeyEventKind::Release {
+        return None;
+    }
+    let seek = |delta: f64| {
+        let frame = (status.frame as f64 + delta * status.fps).round();
+        let frame = (frame.max(1.0) as usize).min(status.frame_count.max(1));
+        TuiInput::Command(ControlCommand::Seek { frame })
+    };
+    let budget = |more: bool| {
+        let level = CompressionLevelArg::try_from(status.compression_level.clone()).ok()?;
+        Some(TuiInput::Command(ControlCommand::CompressionLevel {
+            level: level.step(more).to_string(),
+        }))
+    };
+
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(TuiInput::Quit),
+        KeyCode::Char('q') | KeyCode::Esc => Some(TuiInput::Quit),
+        KeyCode::Char(' ') | KeyCode::Char('p') => Some(TuiInput::Command(match status.paused {
+            true => ControlCommand::Resume,
+            false => ControlCommand::Pause,
+        })),
+        KeyCode::Left => Some(seek(-SEEK_STEP)),
+        KeyCode::Right => Some(seek(SEEK_STEP)),
+        KeyCode::Up => budget(true),
+        KeyCode::Down => budget(false),
+        _ => None,
+    }
+}
+
+/// `secs` as m:ss
+fn format_time(secs: f64) -> String {
+    let secs = secs as u64;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// `value` with a k/M/G prefix
+fn format_count(value: f64, unit: &str) -> String {
+    match value {
+        v if v >= 1e9 => format!("{:.1} G{}", v / 1e9, unit),
+        v if v >= 1e6 => format!("{:.1} M{}", v / 1e6, unit),
+        v if v >= 1e3 => format!("{:.1} k{}", v / 1e3, unit),
+        v => format!("{:.0} {}", v, unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_input() {
+        let status = PlaybackStatus {
+            input: String::new(),
+            frame: 20,
+            frame_count: 300,
+            fps: 10.0,
+            paused: false,
+            x_offset: 0,
+            y_offset: 0,
+            compression_level: "high".to_string(),
+            jit: true,
+        };
+        let key = |code| key_input(KeyEvent::new(code, KeyModifiers::NONE), &status);
+        let command = |c| Some(TuiInput::Command(c));
+
+        assert_eq!(key(KeyCode::Char(' ')), command(ControlCommand::Pause));
+        assert_eq!(key(KeyCode::Left), command(ControlCommand::Seek { frame: 1 }));
+        assert_eq!(key(KeyCode::Right), command(ControlCommand::Seek { frame: 70 }));
+        assert_eq!(
+            key(KeyCode::Up),
+            command(ControlCommand::CompressionLevel { level: "medium".to_string() })
+        );
+        assert_eq!(
+            key(KeyCode::Down),
+            command(ControlCommand::CompressionLevel { level: "trash-compactor".to_string() })
+        );
+        assert_eq!(key(KeyCode::Char('q')), Some(TuiInput::Quit));
+        assert_eq!(
+            key_input(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), &status),
+            Some(TuiInput::Quit)
+        );
+    }
+}